@@ -0,0 +1,144 @@
+//! Snappy compression of message payloads, as used from p2p protocol version 5.
+
+use crate::errors::ECIESEerror;
+
+/// Largest decompressed payload a peer may send us (16 MiB, matching the RLPx frame size field).
+pub const MAX_DECOMPRESSED_SIZE: usize = (1 << 24) - 1;
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, ECIESEerror> {
+    Ok(snap::raw::Encoder::new().compress_vec(data)?)
+}
+
+/// Decompresses `data`, rejecting it up front if the length announced in the snappy header is
+/// larger than `max_size`. The announced length is attacker controlled, so it is checked before
+/// any output buffer is allocated.
+pub fn decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>, ECIESEerror> {
+    let size = snap::raw::decompress_len(data)?;
+    if size > max_size {
+        return Err(ECIESEerror::OversizedFrame {
+            size,
+            max: max_size,
+        });
+    }
+    Ok(snap::raw::Decoder::new().decompress_vec(data)?)
+}
+
+/// What to do with a message body that arrived intact but is not valid snappy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DecompressionMode {
+    /// Fail, as the spec requires once both sides have negotiated version 5.
+    #[default]
+    Strict,
+    /// Take the body as uncompressed, for peers that compress inconsistently while upgrading.
+    Lenient,
+}
+
+/// A payload returned by [`decompress_with_mode`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decompressed {
+    Snappy(Vec<u8>),
+    /// The body was not valid snappy and was passed through as is. Callers should log this, as
+    /// it points at a misbehaving peer.
+    Uncompressed(Vec<u8>),
+}
+
+impl Decompressed {
+    pub fn into_inner(self) -> Vec<u8> {
+        match self {
+            Self::Snappy(data) | Self::Uncompressed(data) => data,
+        }
+    }
+}
+
+/// [`decompress`], falling back to the raw body in [`DecompressionMode::Lenient`]. Only a body
+/// that snappy fails to decode falls back; one announcing more than `max_size` is rejected in
+/// either mode, and the raw body is still held to `max_size`.
+pub fn decompress_with_mode(
+    data: &[u8],
+    max_size: usize,
+    mode: DecompressionMode,
+) -> Result<Decompressed, ECIESEerror> {
+    match decompress(data, max_size) {
+        Ok(decompressed) => Ok(Decompressed::Snappy(decompressed)),
+        Err(ECIESEerror::Other(err))
+            if mode == DecompressionMode::Lenient
+                && err.is::<snap::Error>()
+                && data.len() <= max_size =>
+        {
+            Ok(Decompressed::Uncompressed(data.to_vec()))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let data = vec![7_u8; 4096];
+        let compressed = compress(&data).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(
+            decompress(&compressed, MAX_DECOMPRESSED_SIZE).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_announced_length() {
+        // Varint header claiming a 1 GiB output, followed by a few bytes of junk.
+        let mut crafted = vec![0x80, 0x80, 0x80, 0x80, 0x04];
+        crafted.extend_from_slice(&[0; 8]);
+
+        assert!(matches!(
+            decompress(&crafted, MAX_DECOMPRESSED_SIZE),
+            Err(ECIESEerror::OversizedFrame { size, max })
+                if size == 1 << 30 && max == MAX_DECOMPRESSED_SIZE
+        ));
+    }
+
+    #[test]
+    fn lenient_mode_still_rejects_oversized_announced_length() {
+        let mut crafted = vec![0x80, 0x80, 0x80, 0x80, 0x04];
+        crafted.extend_from_slice(&[0; 8]);
+
+        assert!(matches!(
+            decompress_with_mode(&crafted, MAX_DECOMPRESSED_SIZE, DecompressionMode::Lenient),
+            Err(ECIESEerror::OversizedFrame { size, .. }) if size == 1 << 30
+        ));
+    }
+
+    #[test]
+    fn invalid_snappy_fails_strictly_and_passes_through_leniently() {
+        // A valid length header announcing 16 bytes, followed by a truncated copy element.
+        let not_snappy = [0x10, 0x01];
+        assert!(decompress_with_mode(
+            &not_snappy,
+            MAX_DECOMPRESSED_SIZE,
+            DecompressionMode::default()
+        )
+        .is_err());
+        assert_eq!(
+            decompress_with_mode(
+                &not_snappy,
+                MAX_DECOMPRESSED_SIZE,
+                DecompressionMode::Lenient
+            )
+            .unwrap(),
+            Decompressed::Uncompressed(not_snappy.to_vec())
+        );
+
+        let compressed = compress(b"hello hello hello").unwrap();
+        assert_eq!(
+            decompress_with_mode(
+                &compressed,
+                MAX_DECOMPRESSED_SIZE,
+                DecompressionMode::Lenient
+            )
+            .unwrap(),
+            Decompressed::Snappy(b"hello hello hello".to_vec())
+        );
+    }
+}
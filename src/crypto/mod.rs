@@ -0,0 +1,3 @@
+mod sign;
+
+pub use sign::*;
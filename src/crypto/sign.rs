@@ -0,0 +1,98 @@
+use ethereum_types::H256;
+use secp256k1::{
+    ecdsa::{RecoverableSignature, RecoveryId, Signature},
+    Message, PublicKey, SecretKey, SECP256K1,
+};
+
+/// Signs the 32-byte `msg` and returns the signature as `r || s || v`, where `v` is the
+/// recovery id (0 to 3), as used by the RLPx handshake and discovery packets.
+pub fn sign_recoverable(msg: &H256, sk: &SecretKey) -> [u8; 65] {
+    let (rec_id, sig) = SECP256K1
+        .sign_ecdsa_recoverable(&Message::from_slice(msg.as_bytes()).unwrap(), sk)
+        .serialize_compact();
+
+    let mut out = [0_u8; 65];
+    out[..64].copy_from_slice(&sig);
+    out[64] = rec_id.to_i32() as u8;
+    out
+}
+
+/// Recovers the public key that produced `sig` over `msg`.
+pub fn recover(msg: &H256, sig: &[u8; 65]) -> Result<PublicKey, secp256k1::Error> {
+    let rec_id = RecoveryId::from_i32(i32::from(sig[64]))?;
+    let signature = RecoverableSignature::from_compact(&sig[..64], rec_id)?;
+    SECP256K1.recover_ecdsa(&Message::from_slice(msg.as_bytes())?, &signature)
+}
+
+/// Whether `sig` has its `s` value in the lower half of the curve order, the canonical form
+/// `sign_recoverable` produces. A high-S signature is an equally valid, malleated copy.
+pub fn is_low_s(sig: &[u8; 65]) -> bool {
+    let Ok(signature) = Signature::from_compact(&sig[..64]) else {
+        return false;
+    };
+    let mut normalized = signature;
+    normalized.normalize_s();
+    normalized == signature
+}
+
+/// The high-S twin of a signature: `s` replaced by `n - s` and the recovery id flipped, which
+/// recovers the same key.
+#[cfg(test)]
+pub(crate) fn high_s(sig: &[u8; 65]) -> [u8; 65] {
+    let mut out = *sig;
+    let mut borrow = 0_i16;
+    for i in (0..32).rev() {
+        let diff =
+            i16::from(secp256k1::constants::CURVE_ORDER[i]) - i16::from(sig[32 + i]) - borrow;
+        borrow = i16::from(diff < 0);
+        out[32 + i] = diff.rem_euclid(256) as u8;
+    }
+    out[64] ^= 1;
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::thread_rng;
+
+    #[test]
+    fn sign_then_recover() {
+        let sk = SecretKey::new(&mut thread_rng());
+        let msg = H256::repeat_byte(0x42);
+
+        let sig = sign_recoverable(&msg, &sk);
+        assert_eq!(
+            recover(&msg, &sig).unwrap(),
+            PublicKey::from_secret_key(SECP256K1, &sk)
+        );
+    }
+
+    #[test]
+    fn tampered_signature_does_not_recover_signer() {
+        let sk = SecretKey::new(&mut thread_rng());
+        let msg = H256::repeat_byte(0x42);
+        let mut sig = sign_recoverable(&msg, &sk);
+        sig[10] ^= 1;
+
+        assert_ne!(
+            recover(&msg, &sig).ok(),
+            Some(PublicKey::from_secret_key(SECP256K1, &sk))
+        );
+
+        sig[64] = 4;
+        assert!(recover(&msg, &sig).is_err());
+    }
+
+    #[test]
+    fn only_canonical_signatures_are_low_s() {
+        let sk = SecretKey::new(&mut thread_rng());
+        let msg = H256::repeat_byte(0x42);
+        let sig = sign_recoverable(&msg, &sk);
+        assert!(is_low_s(&sig));
+
+        let malleated = high_s(&sig);
+        assert!(!is_low_s(&malleated));
+        assert_eq!(recover(&msg, &malleated), recover(&msg, &sig));
+    }
+}
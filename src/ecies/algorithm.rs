@@ -1,14 +1,46 @@
-use crate::types::PeerId;
+use super::{
+    AuthReplayCache, HandshakeCipher, NonceTracker, Secrets, HANDSHAKE_IV_LEN, HANDSHAKE_KEY_LEN,
+    HANDSHAKE_OVERHEAD, HANDSHAKE_TAG_LEN,
+};
+use crate::{
+    crypto::{is_low_s, recover, sign_recoverable},
+    errors::ECIESEerror,
+    mac::MAC,
+    types::PeerId,
+    util::{check_rlp_depth, hmac_sha256, id2pk, keccak256, pk2id, sha256, DEFAULT_MAX_RLP_DEPTH},
+};
+use aes::{cipher::KeyIvInit, cipher::StreamCipher};
+use bytes::{Bytes, BytesMut};
 use educe::Educe;
-use ethereum_types::H256;
-use secp256k1::{PublicKey, SecretKey};
+use ethereum_types::{H128, H256};
+use rand::{thread_rng, Rng};
+use rlp::{Rlp, RlpStream};
+use secp256k1::{PublicKey, SecretKey, SECP256K1};
 use sha2::{digest::Digest, Sha256};
+use sha3::Keccak256;
+use std::ops::Range;
+use tracing::{debug, instrument};
 
 const PROTOCOL_VERSION: usize = 4;
 
+/// Size of a pre-EIP-8 `auth` packet: ECIES overhead plus
+/// `sig || keccak256(ephemeral-pubk) || pubk || nonce || 0x00`. It has no size prefix.
+pub(crate) const LEGACY_AUTH_LEN: usize = HANDSHAKE_OVERHEAD + 194;
+
+/// Where the plaintext sits in a pre-EIP-8 `auth` once it is decrypted in place: after the
+/// ephemeral public key and IV, before the tag.
+pub(crate) const LEGACY_AUTH_PLAINTEXT: Range<usize> =
+    65 + HANDSHAKE_IV_LEN..LEGACY_AUTH_LEN - HANDSHAKE_TAG_LEN;
+
+/// Largest `auth` or `ack` packet accepted, including the size prefix. EIP-8 allows arbitrary
+/// padding, but honest packets stay well below this.
+pub const MAX_HANDSHAKE_PACKET_LEN: usize = 2048;
+
+/// X coordinate of the shared point. `SharedSecret::new` would hash the point instead, which
+/// no other RLPx implementation does.
 fn ecdh_x(public_key: &PublicKey, secret_key: &SecretKey) -> H256 {
-    let shared_secret = secp256k1::ecdh::SharedSecret::new(public_key, secret_key);
-    H256::from_slice(&shared_secret.secret_bytes())
+    let point = secp256k1::ecdh::shared_secret_point(public_key, secret_key);
+    H256::from_slice(&point[..32])
 }
 
 fn kdf(secret: H256, s1: &[u8], dest: &mut [u8]) {
@@ -22,7 +54,7 @@ fn kdf(secret: H256, s1: &[u8], dest: &mut [u8]) {
             (ctr >> 8) as u8,
             ctr as u8,
         ];
-        hasher.update(&ctrs);
+        hasher.update(ctrs);
         hasher.update(secret.as_bytes());
         hasher.update(s1);
         let d = hasher.finalize();
@@ -32,6 +64,50 @@ fn kdf(secret: H256, s1: &[u8], dest: &mut [u8]) {
     }
 }
 
+pub(crate) fn split_at_mut<T>(
+    arr: &mut [T],
+    idx: usize,
+) -> Result<(&mut [T], &mut [T]), ECIESEerror> {
+    if idx > arr.len() {
+        return Err(anyhow::anyhow!("idx {} out of bounds {}", idx, arr.len()).into());
+    }
+    Ok(arr.split_at_mut(idx))
+}
+
+/// Which side of the handshake this `ECIES` instance plays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeRole {
+    /// We dialed the peer and send `auth`.
+    Initiator,
+    /// The peer dialed us and we answer with `ack`.
+    Responder,
+}
+
+/// Progress of the auth/ack exchange.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakePhase {
+    /// Nothing has been sent or received yet.
+    Start,
+    /// The initiator sent `auth`, or the responder received it.
+    Auth,
+    /// The `ack` has been sent or received; the handshake is complete.
+    Ack,
+}
+
+/// Raw handshake packets kept for debugging interop failures, e.g. to compare against what
+/// go-ethereum sends. Only recorded when enabled with [`ECIES::set_record_transcript`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HandshakeTranscript {
+    /// The `auth` packet as sent or received, including its size prefix.
+    pub auth: Option<Bytes>,
+    /// The decrypted `auth` body, including EIP-8 padding.
+    pub auth_plaintext: Option<Bytes>,
+    /// The `ack` packet as sent or received, including its size prefix.
+    pub ack: Option<Bytes>,
+    /// The decrypted `ack` body, including EIP-8 padding.
+    pub ack_plaintext: Option<Bytes>,
+}
+
 #[derive(Educe)]
 #[educe(Debug)]
 pub struct ECIES {
@@ -51,5 +127,1118 @@ pub struct ECIES {
     nonce: H256,
     remote_nonce: Option<H256>,
 
+    role: HandshakeRole,
+    phase: HandshakePhase,
+
+    init_msg: Option<Bytes>,
+    remote_init_msg: Option<Bytes>,
+    /// The initiator used the pre-EIP-8 handshake, so our `ack` has to as well.
+    legacy: bool,
+    /// Reject `auth` signatures that are not in low-S form.
+    require_low_s: bool,
+
+    secrets: Option<Secrets>,
+
+    transcript: Option<HandshakeTranscript>,
+}
+
+impl ECIES {
+    fn new_static(
+        secret_key: SecretKey,
+        remote_id: Option<PeerId>,
+        nonce: H256,
+        ephemeral_secret_key: SecretKey,
+        role: HandshakeRole,
+    ) -> Result<Self, ECIESEerror> {
+        let public_key = PublicKey::from_secret_key(SECP256K1, &secret_key);
+        let remote_public_key = remote_id.map(id2pk).transpose()?;
+        let ephemeral_public_key = PublicKey::from_secret_key(SECP256K1, &ephemeral_secret_key);
+
+        Ok(Self {
+            secret_key,
+            public_key,
+            remote_public_key,
+            remote_id,
+            ephemeral_secret_key,
+            ephemeral_public_key,
+            ephemeral_shared_secret: None,
+            remote_ephemeral_public_key: None,
+            nonce,
+            remote_nonce: None,
+            role,
+            phase: HandshakePhase::Start,
+            init_msg: None,
+            remote_init_msg: None,
+            legacy: false,
+            require_low_s: true,
+            secrets: None,
+            transcript: None,
+        })
+    }
+
+    pub fn new_static_client(
+        secret_key: SecretKey,
+        remote_id: PeerId,
+        nonce: H256,
+        ephemeral_secret_key: SecretKey,
+    ) -> Result<Self, ECIESEerror> {
+        Self::new_static(
+            secret_key,
+            Some(remote_id),
+            nonce,
+            ephemeral_secret_key,
+            HandshakeRole::Initiator,
+        )
+    }
+
+    pub fn new_client(secret_key: SecretKey, remote_id: PeerId) -> Result<Self, ECIESEerror> {
+        let nonce = H256::from(thread_rng().gen::<[u8; 32]>());
+        let ephemeral_secret_key = SecretKey::new(&mut thread_rng());
+        Self::new_static_client(secret_key, remote_id, nonce, ephemeral_secret_key)
+    }
+
+    pub fn new_static_server(
+        secret_key: SecretKey,
+        nonce: H256,
+        ephemeral_secret_key: SecretKey,
+    ) -> Result<Self, ECIESEerror> {
+        Self::new_static(
+            secret_key,
+            None,
+            nonce,
+            ephemeral_secret_key,
+            HandshakeRole::Responder,
+        )
+    }
+
+    pub fn new_server(secret_key: SecretKey) -> Result<Self, ECIESEerror> {
+        let nonce = H256::from(thread_rng().gen::<[u8; 32]>());
+        let ephemeral_secret_key = SecretKey::new(&mut thread_rng());
+        Self::new_static_server(secret_key, nonce, ephemeral_secret_key)
+    }
+
+    pub fn remote_id(&self) -> Option<PeerId> {
+        self.remote_id
+    }
+
+    /// Sets the peer we expect to talk to, e.g. once it has been learned from discovery. The id
+    /// must decode to a point on the curve; on error nothing is changed. Only an initiator that
+    /// has not created `auth` yet may do this; otherwise this fails with
+    /// `UnexpectedHandshakeState`, as the peer is then fixed by the handshake.
+    pub fn set_remote_id(&mut self, id: PeerId) -> Result<(), ECIESEerror> {
+        self.expect_state(HandshakeRole::Initiator, HandshakePhase::Start)?;
+        self.set_remote_public_key(id2pk(id)?)
+    }
+
+    /// [`ECIES::set_remote_id`] for a key that is already parsed. The id is derived from the
+    /// key so the two cannot disagree; the static shared secret is computed from the key when
+    /// `auth` is created, so nothing derived from an earlier key is kept.
+    pub fn set_remote_public_key(
+        &mut self,
+        remote_public_key: PublicKey,
+    ) -> Result<(), ECIESEerror> {
+        self.expect_state(HandshakeRole::Initiator, HandshakePhase::Start)?;
+        self.remote_id = Some(pk2id(&remote_public_key));
+        self.remote_public_key = Some(remote_public_key);
+        Ok(())
+    }
+
+    /// Our nonce for this handshake.
+    pub fn nonce(&self) -> H256 {
+        self.nonce
+    }
+
+    /// Claims our nonce in `tracker`, failing if it was used by a recent handshake. Call before
+    /// sending `auth` or `ack`.
+    pub fn claim_nonce(&self, tracker: &mut NonceTracker) -> Result<(), ECIESEerror> {
+        tracker.insert(self.nonce)
+    }
+
+    /// Claims the received `auth` packet in `cache`, failing with `InvalidAuthData` if the same
+    /// packet was recently seen. Call after [`ECIES::parse_auth`], before sending `ack`.
+    pub fn claim_auth(&self, cache: &mut AuthReplayCache) -> Result<(), ECIESEerror> {
+        self.expect_state(HandshakeRole::Responder, HandshakePhase::Auth)?;
+        cache.insert(self.remote_init_msg.as_ref().unwrap())
+    }
+
+    /// Whether an `auth` signature with a high `s` value is rejected. On by default: honest
+    /// signers produce low-S signatures, and a high-S one is a malleated copy.
+    pub fn set_require_low_s(&mut self, require: bool) {
+        self.require_low_s = require;
+    }
+
+    pub fn role(&self) -> HandshakeRole {
+        self.role
+    }
+
+    pub fn phase(&self) -> HandshakePhase {
+        self.phase
+    }
+
+    /// Starts or stops keeping the raw handshake packets. Off by default, as the packets are
+    /// only useful for debugging.
+    pub fn set_record_transcript(&mut self, record: bool) {
+        self.transcript = record.then(HandshakeTranscript::default);
+    }
+
+    pub fn transcript(&self) -> Option<&HandshakeTranscript> {
+        self.transcript.as_ref()
+    }
 
+    fn record(&mut self, f: impl FnOnce(&mut HandshakeTranscript)) {
+        if let Some(transcript) = &mut self.transcript {
+            f(transcript);
+        }
+    }
+
+    /// Frame state, available once the handshake has completed.
+    pub fn secrets_mut(&mut self) -> Option<&mut Secrets> {
+        self.secrets.as_mut()
+    }
+
+    /// The derived `(aes-secret, mac-secret)`, for checking known-answer vectors. Only built
+    /// with the `testing` feature, so key material cannot be read out of a release build.
+    ///
+    /// Panics if the handshake has not completed.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn debug_secrets(&self) -> (H256, H256) {
+        let secrets = self.secrets.as_ref().expect("handshake not complete");
+        (secrets.aes_secret(), secrets.mac_secret())
+    }
+
+    /// Fails unless we play `role` and the handshake is currently at `phase`.
+    fn expect_state(&self, role: HandshakeRole, phase: HandshakePhase) -> Result<(), ECIESEerror> {
+        if self.role != role || self.phase != phase {
+            return Err(ECIESEerror::UnexpectedHandshakeState);
+        }
+        Ok(())
+    }
+
+    /// ECIES-encrypts `data` to the remote. `auth_data` is covered by the tag but not written.
+    fn encrypt_message(&self, data: &[u8], auth_data: &[u8], out: &mut BytesMut) {
+        let secret_key = SecretKey::new(&mut thread_rng());
+        out.extend_from_slice(
+            &PublicKey::from_secret_key(SECP256K1, &secret_key).serialize_uncompressed(),
+        );
+
+        let x = ecdh_x(&self.remote_public_key.unwrap(), &secret_key);
+        let mut key = [0_u8; 32];
+        kdf(x, &[], &mut key);
+
+        let enc_key = H128::from_slice(&key[..HANDSHAKE_KEY_LEN]);
+        let mac_key = sha256(&key[HANDSHAKE_KEY_LEN..]);
+
+        let iv = H128::from(thread_rng().gen::<[u8; HANDSHAKE_IV_LEN]>());
+        let mut encryptor = HandshakeCipher::new(enc_key.as_ref().into(), iv.as_ref().into());
+
+        let mut encrypted = data.to_vec();
+        encryptor.apply_keystream(&mut encrypted);
+
+        let tag = hmac_sha256(mac_key.as_ref(), &[iv.as_bytes(), &encrypted], auth_data);
+
+        out.extend_from_slice(iv.as_bytes());
+        out.extend_from_slice(&encrypted);
+        out.extend_from_slice(tag.as_ref());
+    }
+
+    /// Decrypts a packet prefixed with its two-byte size, which is authenticated as well.
+    fn decrypt_message<'a>(&self, data: &'a mut [u8]) -> Result<&'a mut [u8], ECIESEerror> {
+        let (auth_data, encrypted) = split_at_mut(data, 2)?;
+        self.decrypt(auth_data, encrypted)
+    }
+
+    fn decrypt<'a>(
+        &self,
+        auth_data: &[u8],
+        encrypted: &'a mut [u8],
+    ) -> Result<&'a mut [u8], ECIESEerror> {
+        let (pubkey_bytes, encrypted) = split_at_mut(encrypted, 65)?;
+        let public_key = PublicKey::from_slice(pubkey_bytes)?;
+        let tag_index = encrypted
+            .len()
+            .checked_sub(HANDSHAKE_TAG_LEN)
+            .ok_or(ECIESEerror::TagCheckFailed)?;
+        let (data_iv, tag_bytes) = split_at_mut(encrypted, tag_index)?;
+        let (iv, encrypted_data) = split_at_mut(data_iv, HANDSHAKE_IV_LEN)?;
+        let tag = H256::from_slice(tag_bytes);
+
+        let x = ecdh_x(&public_key, &self.secret_key);
+        let mut key = [0_u8; 32];
+        kdf(x, &[], &mut key);
+        let enc_key = H128::from_slice(&key[..HANDSHAKE_KEY_LEN]);
+        let mac_key = sha256(&key[HANDSHAKE_KEY_LEN..]);
+
+        let check_tag = hmac_sha256(mac_key.as_ref(), &[iv, encrypted_data], auth_data);
+        if check_tag != tag {
+            return Err(ECIESEerror::TagCheckFailed);
+        }
+
+        let decrypted_data = encrypted_data;
+
+        let mut decryptor = HandshakeCipher::new(enc_key.as_ref().into(), (*iv).into());
+        decryptor.apply_keystream(decrypted_data);
+
+        Ok(decrypted_data)
+    }
+
+    /// Prefixes `unencrypted` with its encrypted size and ECIES-encrypts it to the remote.
+    fn seal(&self, unencrypted: &[u8]) -> BytesMut {
+        let mut out = BytesMut::new();
+        let total_size = u16::try_from(HANDSHAKE_OVERHEAD + unencrypted.len()).unwrap();
+        out.extend_from_slice(&total_size.to_be_bytes());
+        self.encrypt_message(unencrypted, &total_size.to_be_bytes(), &mut out);
+        out
+    }
+
+    /// Decrypts `data` in place if it is a complete pre-EIP-8 `auth` packet for us, leaving
+    /// the plaintext at [`LEGACY_AUTH_PLAINTEXT`]. Such packets have no size prefix and start
+    /// with the ECIES public key, so they can only be recognized by decrypting. Returns `false`
+    /// with `data` untouched otherwise, as the tag is checked before anything is decrypted.
+    pub(crate) fn decrypt_legacy_auth(&self, data: &mut [u8]) -> bool {
+        data.len() == LEGACY_AUTH_LEN && data[0] == 0x04 && self.decrypt(&[], data).is_ok()
+    }
+
+    fn create_auth_unencrypted(&self) -> BytesMut {
+        let x = ecdh_x(&self.remote_public_key.unwrap(), &self.secret_key);
+        let sig_bytes = sign_recoverable(&(x ^ self.nonce), &self.ephemeral_secret_key);
+
+        let mut stream = RlpStream::new_list(4);
+        stream.append(&sig_bytes.as_slice());
+        stream.append(&pk2id(&self.public_key));
+        stream.append(&self.nonce);
+        stream.append(&PROTOCOL_VERSION);
+
+        let mut out = stream.out();
+        out.resize(out.len() + thread_rng().gen_range(100..=300), 0);
+        out
+    }
+
+    /// Builds the EIP-8 `auth` packet. Only valid for an initiator that has not sent one yet.
+    #[instrument(level = "debug", skip_all, fields(role = ?self.role, remote_id = ?self.remote_id))]
+    pub fn create_auth(&mut self) -> Result<BytesMut, ECIESEerror> {
+        self.expect_state(HandshakeRole::Initiator, HandshakePhase::Start)?;
+
+        let unencrypted = self.create_auth_unencrypted();
+        let out = self.seal(&unencrypted);
+        self.init_msg = Some(Bytes::copy_from_slice(&out));
+        self.record(|transcript| {
+            transcript.auth = Some(Bytes::copy_from_slice(&out));
+            transcript.auth_plaintext = Some(unencrypted.freeze());
+        });
+
+        self.phase = HandshakePhase::Auth;
+        debug!(len = out.len(), "created auth");
+        Ok(out)
+    }
+
+    fn parse_auth_unencrypted(&mut self, data: &[u8]) -> Result<(), ECIESEerror> {
+        check_rlp_depth(data, DEFAULT_MAX_RLP_DEPTH)?;
+        let rlp = Rlp::new(data);
+
+        let sigdata: Vec<u8> = rlp.val_at(0)?;
+        let remote_id: PeerId = rlp.val_at(1)?;
+        let remote_nonce: H256 = rlp.val_at(2)?;
+
+        self.accept_auth(&sigdata, remote_id, remote_nonce, None)
+    }
+
+    /// Parses the fixed layout `sig || keccak256(ephemeral-pubk) || pubk || nonce || 0x00`.
+    fn parse_legacy_auth_unencrypted(&mut self, data: &[u8]) -> Result<(), ECIESEerror> {
+        if data.len() != 194 {
+            return Err(ECIESEerror::InvalidAuthData);
+        }
+        let (sigdata, rest) = data.split_at(65);
+        let (ephemeral_key_hash, rest) = rest.split_at(32);
+        let (remote_id, rest) = rest.split_at(64);
+
+        self.accept_auth(
+            sigdata,
+            PeerId::from_slice(remote_id),
+            H256::from_slice(&rest[..32]),
+            Some(H256::from_slice(ephemeral_key_hash)),
+        )
+    }
+
+    /// Takes over the fields of a decoded `auth` once its signature checks out. Legacy packets
+    /// also carry the hash of the ephemeral key, which must match the recovered key.
+    fn accept_auth(
+        &mut self,
+        sigdata: &[u8],
+        remote_id: PeerId,
+        remote_nonce: H256,
+        ephemeral_key_hash: Option<H256>,
+    ) -> Result<(), ECIESEerror> {
+        let remote_public_key = id2pk(remote_id)?;
+        let remote_ephemeral_public_key =
+            self.recover_remote_ephemeral_key(sigdata, &remote_public_key, remote_nonce)?;
+        if ephemeral_key_hash
+            .is_some_and(|hash| hash != keccak256(pk2id(&remote_ephemeral_public_key).as_bytes()))
+        {
+            return Err(ECIESEerror::InvalidAuthData);
+        }
+
+        self.remote_id = Some(remote_id);
+        self.remote_public_key = Some(remote_public_key);
+        self.remote_nonce = Some(remote_nonce);
+        self.ephemeral_shared_secret = Some(ecdh_x(
+            &remote_ephemeral_public_key,
+            &self.ephemeral_secret_key,
+        ));
+        self.remote_ephemeral_public_key = Some(remote_ephemeral_public_key);
+
+        Ok(())
+    }
+
+    /// Recovers the initiator's ephemeral key from its signature over
+    /// `static_shared_secret ^ nonce`.
+    ///
+    /// The signature is made with the ephemeral key, so the claimed static key cannot be checked
+    /// directly. It is bound through the signed message instead: only the owner of
+    /// `remote_public_key` can compute the static shared secret, so a peer claiming an id it does
+    /// not control recovers to a key it holds no secret for and can never produce a valid frame.
+    fn recover_remote_ephemeral_key(
+        &self,
+        sigdata: &[u8],
+        remote_public_key: &PublicKey,
+        remote_nonce: H256,
+    ) -> Result<PublicKey, ECIESEerror> {
+        let sigdata = sigdata
+            .try_into()
+            .map_err(|_| ECIESEerror::InvalidAuthData)?;
+        if self.require_low_s && !is_low_s(sigdata) {
+            return Err(ECIESEerror::InvalidAuthData);
+        }
+
+        let x = ecdh_x(remote_public_key, &self.secret_key);
+        recover(&(x ^ remote_nonce), sigdata).map_err(|_| ECIESEerror::InvalidAuthData)
+    }
+
+    /// Parses the initiator's `auth` packet, including its two-byte size prefix. A pre-EIP-8
+    /// packet, recognized by its fixed size, is accepted as well and answered in kind by
+    /// [`ECIES::create_ack`].
+    pub fn parse_auth(&mut self, data: &mut [u8]) -> Result<(), ECIESEerror> {
+        self.expect_state(HandshakeRole::Responder, HandshakePhase::Start)?;
+        if data.len() > MAX_HANDSHAKE_PACKET_LEN {
+            return Err(ECIESEerror::InvalidAuthData);
+        }
+
+        let packet = Bytes::copy_from_slice(data);
+        if self.decrypt_legacy_auth(data) {
+            self.accept_auth_packet(packet, &data[LEGACY_AUTH_PLAINTEXT], true)
+        } else {
+            let unencrypted = self.decrypt_message(data)?;
+            self.accept_auth_packet(packet, unencrypted, false)
+        }
+    }
+
+    /// Parses the plaintext of the `auth` `packet`, which the caller has already decrypted
+    /// and, for a pre-EIP-8 packet, recognized with [`ECIES::decrypt_legacy_auth`].
+    #[instrument(name = "parse_auth", level = "debug", skip_all, fields(role = ?self.role))]
+    pub(crate) fn accept_auth_packet(
+        &mut self,
+        packet: Bytes,
+        unencrypted: &[u8],
+        legacy: bool,
+    ) -> Result<(), ECIESEerror> {
+        self.expect_state(HandshakeRole::Responder, HandshakePhase::Start)?;
+
+        self.record(|transcript| {
+            transcript.auth = Some(packet.clone());
+            transcript.auth_plaintext = Some(Bytes::copy_from_slice(unencrypted));
+        });
+        self.remote_init_msg = Some(packet);
+        if legacy {
+            self.parse_legacy_auth_unencrypted(unencrypted)?;
+        } else {
+            self.parse_auth_unencrypted(unencrypted)?;
+        }
+        self.legacy = legacy;
+
+        self.phase = HandshakePhase::Auth;
+        debug!(legacy, remote_id = ?self.remote_id, "parsed auth");
+        Ok(())
+    }
+
+    fn create_ack_unencrypted(&self) -> BytesMut {
+        let mut stream = RlpStream::new_list(3);
+        stream.append(&pk2id(&self.ephemeral_public_key));
+        stream.append(&self.nonce);
+        stream.append(&PROTOCOL_VERSION);
+
+        let mut out = stream.out();
+        out.resize(out.len() + thread_rng().gen_range(100..=300), 0);
+        out
+    }
+
+    /// `ephemeral-pubk || nonce || 0x00`, the pre-EIP-8 `ack` body.
+    fn create_legacy_ack_unencrypted(&self) -> BytesMut {
+        let mut out = BytesMut::with_capacity(97);
+        out.extend_from_slice(pk2id(&self.ephemeral_public_key).as_bytes());
+        out.extend_from_slice(self.nonce.as_bytes());
+        out.extend_from_slice(&[0]);
+        out
+    }
+
+    /// Builds the `ack` packet, in the pre-EIP-8 format if the `auth` used it. Only valid for a
+    /// responder that has parsed an `auth`.
+    #[instrument(level = "debug", skip_all, fields(role = ?self.role, remote_id = ?self.remote_id))]
+    pub fn create_ack(&mut self) -> Result<BytesMut, ECIESEerror> {
+        self.expect_state(HandshakeRole::Responder, HandshakePhase::Auth)?;
+
+        let (unencrypted, out) = if self.legacy {
+            let unencrypted = self.create_legacy_ack_unencrypted();
+            let mut out = BytesMut::new();
+            self.encrypt_message(&unencrypted, &[], &mut out);
+            (unencrypted, out)
+        } else {
+            let unencrypted = self.create_ack_unencrypted();
+            let out = self.seal(&unencrypted);
+            (unencrypted, out)
+        };
+        self.init_msg = Some(Bytes::copy_from_slice(&out));
+        self.record(|transcript| {
+            transcript.ack = Some(Bytes::copy_from_slice(&out));
+            transcript.ack_plaintext = Some(unencrypted.freeze());
+        });
+
+        self.setup_frame(true);
+        self.phase = HandshakePhase::Ack;
+        debug!(legacy = self.legacy, len = out.len(), "created ack");
+        Ok(out)
+    }
+
+    fn parse_ack_unencrypted(&mut self, data: &[u8]) -> Result<(), ECIESEerror> {
+        check_rlp_depth(data, DEFAULT_MAX_RLP_DEPTH)?;
+        let rlp = Rlp::new(data);
+
+        let remote_ephemeral_public_key = id2pk(rlp.val_at(0)?)?;
+        let remote_nonce: H256 = rlp.val_at(1)?;
+        // A responder echoing our nonce indicates a replay or a broken peer.
+        if remote_nonce == self.nonce {
+            return Err(ECIESEerror::InvalidAckData);
+        }
+
+        self.remote_nonce = Some(remote_nonce);
+        self.ephemeral_shared_secret = Some(ecdh_x(
+            &remote_ephemeral_public_key,
+            &self.ephemeral_secret_key,
+        ));
+        self.remote_ephemeral_public_key = Some(remote_ephemeral_public_key);
+
+        Ok(())
+    }
+
+    /// Parses the responder's `ack` packet, including its two-byte size prefix.
+    #[instrument(level = "debug", skip_all, fields(role = ?self.role, remote_id = ?self.remote_id))]
+    pub fn parse_ack(&mut self, data: &mut [u8]) -> Result<(), ECIESEerror> {
+        self.expect_state(HandshakeRole::Initiator, HandshakePhase::Auth)?;
+        if data.len() > MAX_HANDSHAKE_PACKET_LEN {
+            return Err(ECIESEerror::InvalidAckData);
+        }
+
+        self.remote_init_msg = Some(Bytes::copy_from_slice(data));
+        self.record(|transcript| transcript.ack = Some(Bytes::copy_from_slice(data)));
+        let unencrypted = self.decrypt_message(data)?;
+        self.record(|transcript| {
+            transcript.ack_plaintext = Some(Bytes::copy_from_slice(unencrypted))
+        });
+        self.parse_ack_unencrypted(unencrypted)?;
+
+        self.setup_frame(false);
+        self.phase = HandshakePhase::Ack;
+        debug!("parsed ack");
+        Ok(())
+    }
+
+    /// Derives the frame secrets from the completed handshake. `incoming` is true for the
+    /// responder. Only the direction is logged, never the derived secrets.
+    #[instrument(level = "debug", skip(self))]
+    fn setup_frame(&mut self, incoming: bool) {
+        let ephemeral_shared_secret = self.ephemeral_shared_secret.unwrap();
+        let remote_nonce = self.remote_nonce.unwrap();
+
+        let (recipient_nonce, initiator_nonce) = if incoming {
+            (self.nonce, remote_nonce)
+        } else {
+            (remote_nonce, self.nonce)
+        };
+        let h_nonce = H256::from_slice(
+            &Keccak256::new()
+                .chain_update(recipient_nonce)
+                .chain_update(initiator_nonce)
+                .finalize(),
+        );
+
+        let shared_secret = H256::from_slice(
+            &Keccak256::new()
+                .chain_update(ephemeral_shared_secret)
+                .chain_update(h_nonce)
+                .finalize(),
+        );
+        let aes_secret = H256::from_slice(
+            &Keccak256::new()
+                .chain_update(ephemeral_shared_secret)
+                .chain_update(shared_secret)
+                .finalize(),
+        );
+        let mac_secret = H256::from_slice(
+            &Keccak256::new()
+                .chain_update(ephemeral_shared_secret)
+                .chain_update(aes_secret)
+                .finalize(),
+        );
+
+        // Each side seeds its egress MAC with the nonce it received and the packet it sent, and
+        // its ingress MAC with its own nonce and the packet it received, so one side's egress
+        // state always equals the other side's ingress state.
+        let ingress_mac = MAC::seeded(
+            mac_secret,
+            self.nonce,
+            self.remote_init_msg.as_ref().unwrap(),
+        );
+        let egress_mac = MAC::seeded(mac_secret, remote_nonce, self.init_msg.as_ref().unwrap());
+
+        self.secrets = Some(Secrets::new(aes_secret, ingress_mac, egress_mac));
+        debug!("derived frame secrets");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecies::DEFAULT_HEADER_DATA;
+    use std::sync::{Arc, Mutex};
+
+    fn handshake() -> (ECIES, ECIES) {
+        let server_secret_key = SecretKey::new(&mut thread_rng());
+        let server_id = pk2id(&PublicKey::from_secret_key(SECP256K1, &server_secret_key));
+        let client_secret_key = SecretKey::new(&mut thread_rng());
+
+        let mut server = ECIES::new_server(server_secret_key).unwrap();
+        let mut client = ECIES::new_client(client_secret_key, server_id).unwrap();
+
+        let mut auth = client.create_auth().unwrap();
+        server.parse_auth(&mut auth).unwrap();
+        let mut ack = server.create_ack().unwrap();
+        client.parse_ack(&mut ack).unwrap();
+
+        (client, server)
+    }
+
+    #[test]
+    fn handshake_steps_are_traced_without_secrets() {
+        #[derive(Clone, Default)]
+        struct Captured(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Captured {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let (client, server) = tracing::subscriber::with_default(subscriber, handshake);
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        for expected in [
+            "create_auth",
+            "created auth",
+            "parse_auth",
+            "parsed auth",
+            "create_ack",
+            "created ack",
+            "parse_ack",
+            "parsed ack",
+            "setup_frame{incoming=true}",
+            "setup_frame{incoming=false}",
+            "role=Initiator",
+            "role=Responder",
+        ] {
+            assert!(
+                output.contains(expected),
+                "missing {expected:?} in {output}"
+            );
+        }
+
+        let (aes_secret, mac_secret) = client.debug_secrets();
+        for secret in [
+            aes_secret,
+            mac_secret,
+            client.ephemeral_shared_secret.unwrap(),
+            server.ephemeral_shared_secret.unwrap(),
+        ] {
+            assert!(!output.contains(&hex::encode(secret)));
+        }
+    }
+
+    #[test]
+    fn handshake_agrees_on_ephemeral_secret() {
+        let (client, server) = handshake();
+
+        assert_eq!(
+            server.remote_id(),
+            Some(pk2id(&PublicKey::from_secret_key(
+                SECP256K1,
+                &client.secret_key
+            )))
+        );
+        assert_eq!(client.remote_nonce, Some(server.nonce));
+        assert_eq!(server.remote_nonce, Some(client.nonce));
+        assert!(client.ephemeral_shared_secret.is_some());
+        assert_eq!(
+            client.ephemeral_shared_secret,
+            server.ephemeral_shared_secret
+        );
+        assert_eq!(client.phase(), HandshakePhase::Ack);
+        assert_eq!(server.phase(), HandshakePhase::Ack);
+    }
+
+    #[test]
+    fn frames_flow_in_both_directions() {
+        let (mut client, mut server) = handshake();
+
+        // The initiator's MACs, derived independently from the spec:
+        // egress-mac = keccak256.init((mac-secret ^ recipient-nonce) || auth)
+        // ingress-mac = keccak256.init((mac-secret ^ initiator-nonce) || ack)
+        let keccak = |a: &[u8], b: &[u8]| {
+            H256::from_slice(&Keccak256::new().chain_update(a).chain_update(b).finalize())
+        };
+        let h_nonce = keccak(server.nonce.as_bytes(), client.nonce.as_bytes());
+        let ephemeral_shared_secret = client.ephemeral_shared_secret.unwrap();
+        let shared_secret = keccak(ephemeral_shared_secret.as_bytes(), h_nonce.as_bytes());
+        let aes_secret = keccak(ephemeral_shared_secret.as_bytes(), shared_secret.as_bytes());
+        let mac_secret = keccak(ephemeral_shared_secret.as_bytes(), aes_secret.as_bytes());
+
+        let mut egress_mac = MAC::new(mac_secret);
+        egress_mac.update((mac_secret ^ server.nonce).as_bytes());
+        egress_mac.update(client.init_msg.as_ref().unwrap());
+        let mut ingress_mac = MAC::new(mac_secret);
+        ingress_mac.update((mac_secret ^ client.nonce).as_bytes());
+        ingress_mac.update(server.init_msg.as_ref().unwrap());
+        assert_eq!(
+            client.secrets_mut().unwrap().to_bytes(),
+            Secrets::new(aes_secret, ingress_mac, egress_mac).to_bytes()
+        );
+
+        let send = |sender: &mut ECIES, receiver: &mut ECIES| {
+            let mut frame = BytesMut::new();
+            let sender = sender.secrets_mut().unwrap();
+            sender
+                .write_header(&mut frame, 4, &DEFAULT_HEADER_DATA)
+                .unwrap();
+            sender.write_body(&mut frame, b"ping").unwrap();
+
+            let receiver = receiver.secrets_mut().unwrap();
+            let mut body = frame.split_off(Secrets::header_len());
+            assert_eq!(receiver.read_header(&frame).unwrap().body_size, 4);
+            assert_eq!(receiver.read_body(&mut body).unwrap(), b"ping");
+        };
+        send(&mut client, &mut server);
+        send(&mut server, &mut client);
+    }
+
+    #[test]
+    fn session_id_depends_only_on_handshake_inputs() {
+        let client_secret_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let server_secret_key = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let server_id = pk2id(&PublicKey::from_secret_key(SECP256K1, &server_secret_key));
+        let session_ids = |client_nonce: H256| {
+            let mut client = ECIES::new_static_client(
+                client_secret_key,
+                server_id,
+                client_nonce,
+                SecretKey::from_slice(&[0x33; 32]).unwrap(),
+            )
+            .unwrap();
+            let mut server = ECIES::new_static_server(
+                server_secret_key,
+                H256::repeat_byte(0x02),
+                SecretKey::from_slice(&[0x44; 32]).unwrap(),
+            )
+            .unwrap();
+
+            let mut auth = client.create_auth().unwrap();
+            server.parse_auth(&mut auth).unwrap();
+            let mut ack = server.create_ack().unwrap();
+            client.parse_ack(&mut ack).unwrap();
+            (
+                client.secrets_mut().unwrap().session_id(),
+                server.secrets_mut().unwrap().session_id(),
+            )
+        };
+
+        let (client_id, server_id) = session_ids(H256::repeat_byte(0x01));
+        assert_eq!(client_id, server_id);
+        assert_eq!(session_ids(H256::repeat_byte(0x01)), (client_id, server_id));
+        assert_ne!(session_ids(H256::repeat_byte(0x03)).0, client_id);
+    }
+
+    /// Builds the fixed-size `auth` old clients send:
+    /// `sig || keccak256(ephemeral-pubk) || pubk || nonce || 0x00`, encrypted without a size
+    /// prefix.
+    fn legacy_auth(client: &ECIES) -> BytesMut {
+        let rlp = client.create_auth_unencrypted();
+        let rlp = Rlp::new(&rlp);
+        let sigdata: Vec<u8> = rlp.val_at(0).unwrap();
+
+        let mut unencrypted = sigdata;
+        unencrypted.extend_from_slice(
+            keccak256(pk2id(&client.ephemeral_public_key).as_bytes()).as_bytes(),
+        );
+        unencrypted.extend_from_slice(pk2id(&client.public_key).as_bytes());
+        unencrypted.extend_from_slice(client.nonce.as_bytes());
+        unencrypted.push(0);
+
+        let mut out = BytesMut::new();
+        client.encrypt_message(&unencrypted, &[], &mut out);
+        out
+    }
+
+    #[test]
+    fn legacy_auth_is_recognized_by_decrypting_it_once() {
+        let (client, mut server) = auth_pair();
+        let packet = legacy_auth(&client);
+
+        let mut decrypted = packet.clone();
+        assert!(server.decrypt_legacy_auth(&mut decrypted));
+        server
+            .accept_auth_packet(packet.freeze(), &decrypted[LEGACY_AUTH_PLAINTEXT], true)
+            .unwrap();
+        assert_eq!(server.remote_nonce, Some(client.nonce));
+
+        // Anything else is left as it was, for the EIP-8 path to decrypt.
+        let (client, server) = auth_pair();
+        let mut eip8 = client.seal(&client.create_auth_unencrypted());
+        let original = eip8.clone();
+        assert!(!server.decrypt_legacy_auth(&mut eip8));
+        assert_eq!(eip8, original);
+
+        let mut garbage = vec![0x04; LEGACY_AUTH_LEN];
+        assert!(!server.decrypt_legacy_auth(&mut garbage));
+        assert_eq!(garbage, vec![0x04; LEGACY_AUTH_LEN]);
+    }
+
+    #[test]
+    fn legacy_auth_gets_legacy_ack() {
+        let (mut client, mut server) = auth_pair();
+        let mut auth = legacy_auth(&client);
+        assert_eq!(auth.len(), LEGACY_AUTH_LEN);
+        client.init_msg = Some(Bytes::copy_from_slice(&auth));
+
+        server.parse_auth(&mut auth).unwrap();
+        assert_eq!(server.remote_id(), Some(pk2id(&client.public_key)));
+        assert_eq!(server.remote_nonce, Some(client.nonce));
+        assert_eq!(
+            server.remote_ephemeral_public_key,
+            Some(client.ephemeral_public_key)
+        );
+
+        // The answer is a 210-byte ack carrying the responder's ephemeral key and nonce.
+        let mut ack = server.create_ack().unwrap();
+        assert_eq!(ack.len(), 210);
+        let unencrypted = client.decrypt(&[], &mut ack).unwrap();
+        assert_eq!(
+            &unencrypted[..64],
+            pk2id(&server.ephemeral_public_key).as_bytes()
+        );
+        assert_eq!(&unencrypted[64..96], server.nonce.as_bytes());
+        assert_eq!(unencrypted[96], 0);
+    }
+
+    #[test]
+    fn legacy_auth_with_wrong_ephemeral_hash_is_rejected() {
+        let (client, mut server) = auth_pair();
+        let mut auth = legacy_auth(&client);
+        let unencrypted = server.decrypt(&[], &mut auth).unwrap();
+        unencrypted[65] ^= 1;
+        let mut tampered = BytesMut::new();
+        client.encrypt_message(unencrypted, &[], &mut tampered);
+
+        assert!(matches!(
+            server.parse_auth(&mut tampered),
+            Err(ECIESEerror::InvalidAuthData)
+        ));
+    }
+
+    #[test]
+    fn oversized_handshake_packets_are_rejected() {
+        let (mut client, mut server) = auth_pair();
+        let mut unencrypted = client.create_auth_unencrypted();
+        unencrypted.resize(MAX_HANDSHAKE_PACKET_LEN, 0);
+        let mut auth = client.seal(&unencrypted);
+        assert!(matches!(
+            server.parse_auth(&mut auth),
+            Err(ECIESEerror::InvalidAuthData)
+        ));
+
+        server.remote_public_key = Some(client.public_key);
+        let mut ack = server.seal(&unencrypted);
+        assert!(matches!(
+            client.parse_ack(&mut ack),
+            Err(ECIESEerror::InvalidAckData)
+        ));
+    }
+
+    #[test]
+    fn transcript_captures_handshake_packets() {
+        let server_secret_key = SecretKey::new(&mut thread_rng());
+        let server_id = pk2id(&PublicKey::from_secret_key(SECP256K1, &server_secret_key));
+        let mut server = ECIES::new_server(server_secret_key).unwrap();
+        let mut client = ECIES::new_client(SecretKey::new(&mut thread_rng()), server_id).unwrap();
+        assert!(client.transcript().is_none());
+        client.set_record_transcript(true);
+        server.set_record_transcript(true);
+
+        let mut auth = client.create_auth().unwrap();
+        server.parse_auth(&mut auth).unwrap();
+        let mut ack = server.create_ack().unwrap();
+        client.parse_ack(&mut ack).unwrap();
+
+        let client_transcript = client.transcript().unwrap();
+        let server_transcript = server.transcript().unwrap();
+        for transcript in [client_transcript, server_transcript] {
+            assert!(transcript.auth.is_some());
+            assert!(transcript.auth_plaintext.is_some());
+            assert!(transcript.ack.is_some());
+            assert!(transcript.ack_plaintext.is_some());
+        }
+        assert_eq!(client_transcript, server_transcript);
+    }
+
+    #[test]
+    fn set_remote_id_validates_curve_point() {
+        let mut ecies = ECIES::new_client(
+            SecretKey::new(&mut thread_rng()),
+            pk2id(&PublicKey::from_secret_key(
+                SECP256K1,
+                &SecretKey::new(&mut thread_rng()),
+            )),
+        )
+        .unwrap();
+        let remote_public_key =
+            PublicKey::from_secret_key(SECP256K1, &SecretKey::new(&mut thread_rng()));
+        let remote_id = pk2id(&remote_public_key);
+
+        ecies.set_remote_id(remote_id).unwrap();
+        assert_eq!(ecies.remote_id(), Some(remote_id));
+        assert_eq!(ecies.remote_public_key, Some(remote_public_key));
+
+        // An x coordinate above the field prime cannot be on the curve.
+        assert!(ecies.set_remote_id(PeerId::repeat_byte(0xff)).is_err());
+        assert_eq!(ecies.remote_id(), Some(remote_id));
+        assert_eq!(ecies.remote_public_key, Some(remote_public_key));
+    }
+
+    #[test]
+    fn set_remote_public_key_retargets_the_handshake() {
+        let stale_key = SecretKey::new(&mut thread_rng());
+        let server_key = SecretKey::new(&mut thread_rng());
+        let server_public_key = PublicKey::from_secret_key(SECP256K1, &server_key);
+
+        let mut client = ECIES::new_client(
+            SecretKey::new(&mut thread_rng()),
+            pk2id(&PublicKey::from_secret_key(SECP256K1, &stale_key)),
+        )
+        .unwrap();
+        client.set_remote_public_key(server_public_key).unwrap();
+        assert_eq!(client.remote_id(), Some(pk2id(&server_public_key)));
+        assert_eq!(client.remote_public_key, Some(server_public_key));
+
+        // The auth is encrypted to, and its signature bound to, the new key only.
+        let auth = client.create_auth().unwrap();
+        let mut server = ECIES::new_server(server_key).unwrap();
+        server.parse_auth(&mut auth.to_vec()).unwrap();
+        let mut ack = server.create_ack().unwrap();
+        client.parse_ack(&mut ack).unwrap();
+        assert_eq!(
+            client.secrets_mut().unwrap().session_id(),
+            server.secrets_mut().unwrap().session_id()
+        );
+
+        let mut stale = ECIES::new_server(stale_key).unwrap();
+        assert!(stale.parse_auth(&mut auth.to_vec()).is_err());
+    }
+
+    #[test]
+    fn remote_id_is_fixed_once_the_handshake_starts() {
+        let (mut client, mut server) = handshake();
+        let client_id = server.remote_id();
+        let server_id = client.remote_id();
+        let other_id = pk2id(&PublicKey::from_secret_key(
+            SECP256K1,
+            &SecretKey::new(&mut thread_rng()),
+        ));
+
+        for (ecies, id) in [(&mut client, server_id), (&mut server, client_id)] {
+            assert!(matches!(
+                ecies.set_remote_id(other_id),
+                Err(ECIESEerror::UnexpectedHandshakeState)
+            ));
+            assert!(matches!(
+                ecies.set_remote_public_key(id2pk(other_id).unwrap()),
+                Err(ECIESEerror::UnexpectedHandshakeState)
+            ));
+            assert_eq!(ecies.remote_id(), id);
+        }
+
+        // A responder learns its peer from `auth`, never up front.
+        let mut fresh = ECIES::new_server(SecretKey::new(&mut thread_rng())).unwrap();
+        assert!(fresh.set_remote_id(other_id).is_err());
+        assert_eq!(fresh.remote_id(), None);
+    }
+
+    #[test]
+    fn create_ack_before_parse_auth_fails() {
+        let mut server = ECIES::new_server(SecretKey::new(&mut thread_rng())).unwrap();
+
+        assert!(matches!(
+            server.create_ack(),
+            Err(ECIESEerror::UnexpectedHandshakeState)
+        ));
+    }
+
+    #[test]
+    fn create_auth_twice_fails() {
+        let server_id = pk2id(&PublicKey::from_secret_key(
+            SECP256K1,
+            &SecretKey::new(&mut thread_rng()),
+        ));
+        let mut client = ECIES::new_client(SecretKey::new(&mut thread_rng()), server_id).unwrap();
+
+        client.create_auth().unwrap();
+        assert!(matches!(
+            client.create_auth(),
+            Err(ECIESEerror::UnexpectedHandshakeState)
+        ));
+    }
+
+    #[test]
+    fn ack_with_equal_nonce_is_rejected() {
+        let nonce = H256::repeat_byte(0x5a);
+        let server_secret_key = SecretKey::new(&mut thread_rng());
+        let server_id = pk2id(&PublicKey::from_secret_key(SECP256K1, &server_secret_key));
+
+        let mut server =
+            ECIES::new_static_server(server_secret_key, nonce, SecretKey::new(&mut thread_rng()))
+                .unwrap();
+        let mut client = ECIES::new_static_client(
+            SecretKey::new(&mut thread_rng()),
+            server_id,
+            nonce,
+            SecretKey::new(&mut thread_rng()),
+        )
+        .unwrap();
+
+        let mut auth = client.create_auth().unwrap();
+        server.parse_auth(&mut auth).unwrap();
+        let mut ack = server.create_ack().unwrap();
+
+        assert!(matches!(
+            client.parse_ack(&mut ack),
+            Err(ECIESEerror::InvalidAckData)
+        ));
+        assert_eq!(client.phase(), HandshakePhase::Auth);
+    }
+
+    /// Builds an `auth` packet whose signature has been modified by `tamper`. The signature is
+    /// the first item of the RLP list, starting after the list and string headers.
+    fn tampered_auth(client: &ECIES, server: &ECIES, tamper: impl FnOnce(&mut [u8])) -> BytesMut {
+        let mut auth = client.seal(&client.create_auth_unencrypted());
+        let unencrypted = server.decrypt_message(&mut auth).unwrap();
+        tamper(&mut unencrypted[4..69]);
+        client.seal(unencrypted)
+    }
+
+    fn auth_pair() -> (ECIES, ECIES) {
+        let server_secret_key = SecretKey::new(&mut thread_rng());
+        let server_id = pk2id(&PublicKey::from_secret_key(SECP256K1, &server_secret_key));
+        let mut client = ECIES::new_client(SecretKey::new(&mut thread_rng()), server_id).unwrap();
+        client.phase = HandshakePhase::Auth;
+        (client, ECIES::new_server(server_secret_key).unwrap())
+    }
+
+    #[test]
+    fn auth_with_invalid_signature_is_rejected() {
+        let (client, mut server) = auth_pair();
+        let mut auth = tampered_auth(&client, &server, |sig| sig[64] = 7);
+
+        assert!(matches!(
+            server.parse_auth(&mut auth),
+            Err(ECIESEerror::InvalidAuthData)
+        ));
+    }
+
+    #[test]
+    fn high_s_auth_signature_is_rejected_unless_allowed() {
+        let (mut client, mut server) = auth_pair();
+        let malleate = |sig: &mut [u8]| {
+            let high_s = crate::crypto::high_s(&<[u8; 65]>::try_from(&*sig).unwrap());
+            sig.copy_from_slice(&high_s);
+        };
+        let mut auth = tampered_auth(&client, &server, malleate);
+        assert!(matches!(
+            server.parse_auth(&mut auth.clone()),
+            Err(ECIESEerror::InvalidAuthData)
+        ));
+
+        // The malleated signature still recovers the client's ephemeral key.
+        server.set_require_low_s(false);
+        client.init_msg = Some(Bytes::copy_from_slice(&auth));
+        server.parse_auth(&mut auth).unwrap();
+        let mut ack = server.create_ack().unwrap();
+        client.parse_ack(&mut ack).unwrap();
+        assert_eq!(
+            client.secrets_mut().unwrap().session_id(),
+            server.secrets_mut().unwrap().session_id()
+        );
+    }
+
+    #[test]
+    fn auth_with_tampered_signature_yields_no_usable_session() {
+        let (mut client, mut server) = auth_pair();
+        let mut auth = tampered_auth(&client, &server, |sig| sig[63] ^= 1);
+        client.init_msg = Some(Bytes::copy_from_slice(&auth));
+
+        // The tampered signature recovers to a key the client has no secret for.
+        server.parse_auth(&mut auth).unwrap();
+        let mut ack = server.create_ack().unwrap();
+        client.parse_ack(&mut ack).unwrap();
+
+        let mut frame = BytesMut::new();
+        client
+            .secrets_mut()
+            .unwrap()
+            .write_header(&mut frame, 4, &DEFAULT_HEADER_DATA)
+            .unwrap();
+        assert!(matches!(
+            server.secrets_mut().unwrap().read_header(&frame),
+            Err(ECIESEerror::HeaderMacMismatch)
+        ));
+    }
+
+    #[test]
+    fn role_mismatch_fails() {
+        let mut server = ECIES::new_server(SecretKey::new(&mut thread_rng())).unwrap();
+        assert!(matches!(
+            server.create_auth(),
+            Err(ECIESEerror::UnexpectedHandshakeState)
+        ));
+
+        let (mut client, _) = handshake();
+        assert!(matches!(
+            client.create_ack(),
+            Err(ECIESEerror::UnexpectedHandshakeState)
+        ));
+    }
 }
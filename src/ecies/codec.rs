@@ -0,0 +1,515 @@
+use super::{
+    algorithm::{LEGACY_AUTH_LEN, LEGACY_AUTH_PLAINTEXT},
+    AuthReplayCache, NonceTracker, Secrets, ECIES, MAX_FRAME_SIZE, MAX_HANDSHAKE_PACKET_LEN,
+};
+use crate::{errors::ECIESEerror, types::PeerId};
+use bytes::{Bytes, BytesMut};
+use secp256k1::SecretKey;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// What the codec expects to read next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ECIESState {
+    Auth,
+    Ack,
+    /// The handshake is done and frames are exchanged.
+    Header,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IngressECIESValue {
+    AuthReceive(PeerId),
+    Ack,
+    Message(Bytes),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EgressECIESValue {
+    Auth,
+    Ack,
+    Message(Bytes),
+}
+
+/// Tokio codec running the ECIES handshake and then RLPx framing over a byte stream.
+///
+/// Each `decode` call consumes at most one handshake packet or one frame and leaves any
+/// remaining bytes in the buffer for the next call.
+#[derive(Debug)]
+pub struct ECIESCodec {
+    ecies: ECIES,
+    state: ECIESState,
+    max_inbound_frame: usize,
+    max_outbound_frame: usize,
+    nonce_tracker: Option<Arc<Mutex<NonceTracker>>>,
+    auth_replay_cache: Option<Arc<Mutex<AuthReplayCache>>>,
+}
+
+impl ECIESCodec {
+    pub fn new_client(secret_key: SecretKey, remote_id: PeerId) -> Result<Self, ECIESEerror> {
+        Ok(Self {
+            ecies: ECIES::new_client(secret_key, remote_id)?,
+            state: ECIESState::Ack,
+            max_inbound_frame: MAX_FRAME_SIZE,
+            max_outbound_frame: MAX_FRAME_SIZE,
+            nonce_tracker: None,
+            auth_replay_cache: None,
+        })
+    }
+
+    pub fn new_server(secret_key: SecretKey) -> Result<Self, ECIESEerror> {
+        Ok(Self {
+            ecies: ECIES::new_server(secret_key)?,
+            state: ECIESState::Auth,
+            max_inbound_frame: MAX_FRAME_SIZE,
+            max_outbound_frame: MAX_FRAME_SIZE,
+            nonce_tracker: None,
+            auth_replay_cache: None,
+        })
+    }
+
+    pub fn ecies(&self) -> &ECIES {
+        &self.ecies
+    }
+
+    pub fn ecies_mut(&mut self) -> &mut ECIES {
+        &mut self.ecies
+    }
+
+    pub fn state(&self) -> ECIESState {
+        self.state
+    }
+
+    /// Largest frame body accepted from the peer. Larger frames fail with `OversizedFrame`
+    /// as soon as their header is read. Defaults to [`MAX_FRAME_SIZE`].
+    pub fn set_max_inbound_frame(&mut self, max: usize) {
+        self.max_inbound_frame = max.min(MAX_FRAME_SIZE);
+    }
+
+    /// Largest message we may send in one frame. Encoding a larger one fails with
+    /// `OversizedFrame` and writes nothing. Defaults to [`MAX_FRAME_SIZE`].
+    pub fn set_max_outbound_frame(&mut self, max: usize) {
+        self.max_outbound_frame = max.min(MAX_FRAME_SIZE);
+    }
+
+    /// Shares `tracker` with the other connections of this node. Our nonce is claimed in it
+    /// before `auth` or `ack` is sent, and encoding fails with `NonceReused` if a recent
+    /// handshake used the same one.
+    pub fn set_nonce_tracker(&mut self, tracker: Arc<Mutex<NonceTracker>>) {
+        self.nonce_tracker = Some(tracker);
+    }
+
+    /// Shares `cache` with the other connections of this node. Each received `auth` is claimed
+    /// in it, and decoding fails with `InvalidAuthData` if the same packet was recently seen.
+    pub fn set_auth_replay_cache(&mut self, cache: Arc<Mutex<AuthReplayCache>>) {
+        self.auth_replay_cache = Some(cache);
+    }
+
+    fn claim_nonce(&self) -> Result<(), ECIESEerror> {
+        match &self.nonce_tracker {
+            Some(tracker) => self
+                .ecies
+                .claim_nonce(&mut tracker.lock().unwrap_or_else(PoisonError::into_inner)),
+            None => Ok(()),
+        }
+    }
+
+    fn claim_auth(&self) -> Result<(), ECIESEerror> {
+        match &self.auth_replay_cache {
+            Some(cache) => self
+                .ecies
+                .claim_auth(&mut cache.lock().unwrap_or_else(PoisonError::into_inner)),
+            None => Ok(()),
+        }
+    }
+
+    fn secrets(&mut self) -> Result<&mut Secrets, ECIESEerror> {
+        self.ecies
+            .secrets_mut()
+            .ok_or(ECIESEerror::UnexpectedHandshakeState)
+    }
+
+    /// Splits a two-byte size prefixed handshake packet off `buf` once it has fully arrived.
+    /// Fails with `oversized` as soon as the prefix announces more than
+    /// [`MAX_HANDSHAKE_PACKET_LEN`], rather than buffering the padding.
+    fn split_packet(
+        buf: &mut BytesMut,
+        oversized: ECIESEerror,
+    ) -> Result<Option<BytesMut>, ECIESEerror> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let total_size = u16::from_be_bytes([buf[0], buf[1]]) as usize + 2;
+        if total_size > MAX_HANDSHAKE_PACKET_LEN {
+            return Err(oversized);
+        }
+        if buf.len() < total_size {
+            return Ok(None);
+        }
+        Ok(Some(buf.split_to(total_size)))
+    }
+}
+
+impl Decoder for ECIESCodec {
+    type Item = IngressECIESValue;
+    type Error = ECIESEerror;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.state {
+            ECIESState::Auth => {
+                // Old clients send a fixed-size auth without a size prefix. It is decrypted
+                // once here and handed on as plaintext, rather than probed and decrypted again.
+                let mut legacy = BytesMut::from(&buf[..LEGACY_AUTH_LEN.min(buf.len())]);
+                if self.ecies.decrypt_legacy_auth(&mut legacy) {
+                    let packet = buf.split_to(LEGACY_AUTH_LEN).freeze();
+                    self.ecies
+                        .accept_auth_packet(packet, &legacy[LEGACY_AUTH_PLAINTEXT], true)?;
+                } else {
+                    let Some(mut packet) = Self::split_packet(buf, ECIESEerror::InvalidAuthData)?
+                    else {
+                        return Ok(None);
+                    };
+                    self.ecies.parse_auth(&mut packet)?;
+                }
+                self.claim_auth()?;
+                self.state = ECIESState::Header;
+                Ok(Some(IngressECIESValue::AuthReceive(
+                    self.ecies.remote_id().unwrap(),
+                )))
+            }
+            ECIESState::Ack => {
+                let Some(mut packet) = Self::split_packet(buf, ECIESEerror::InvalidAckData)? else {
+                    return Ok(None);
+                };
+                self.ecies.parse_ack(&mut packet)?;
+                self.state = ECIESState::Header;
+                Ok(Some(IngressECIESValue::Ack))
+            }
+            ECIESState::Header => {
+                let max = self.max_inbound_frame;
+                let frame = self.secrets()?.decode_frame_in_place(buf, max)?;
+                Ok(frame.map(|frame| IngressECIESValue::Message(frame.body)))
+            }
+        }
+    }
+}
+
+/// Each item is encrypted in full into `buf` and advances the egress cipher and MAC exactly
+/// once. A short socket write leaves the rest of the frame in `buf` to be retried as-is; it is
+/// never re-encoded.
+impl Encoder<EgressECIESValue> for ECIESCodec {
+    type Error = ECIESEerror;
+
+    fn encode(&mut self, item: EgressECIESValue, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        match item {
+            EgressECIESValue::Auth => {
+                self.claim_nonce()?;
+                buf.extend_from_slice(&self.ecies.create_auth()?);
+                self.state = ECIESState::Ack;
+            }
+            EgressECIESValue::Ack => {
+                self.claim_nonce()?;
+                buf.extend_from_slice(&self.ecies.create_ack()?);
+                self.state = ECIESState::Header;
+            }
+            EgressECIESValue::Message(data) => {
+                if data.len() > self.max_outbound_frame {
+                    return Err(ECIESEerror::OversizedFrame {
+                        size: data.len(),
+                        max: self.max_outbound_frame,
+                    });
+                }
+                buf.extend_from_slice(&self.secrets()?.encode_frame(&data)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::pk2id;
+    use ethereum_types::H256;
+    use rand::thread_rng;
+    use secp256k1::{PublicKey, SECP256K1};
+
+    /// Runs the handshake between two codecs over in-memory buffers.
+    fn connected_pair() -> (ECIESCodec, ECIESCodec) {
+        let server_secret_key = SecretKey::new(&mut thread_rng());
+        let server_id = pk2id(&PublicKey::from_secret_key(SECP256K1, &server_secret_key));
+
+        let mut client =
+            ECIESCodec::new_client(SecretKey::new(&mut thread_rng()), server_id).unwrap();
+        let mut server = ECIESCodec::new_server(server_secret_key).unwrap();
+
+        let mut buf = BytesMut::new();
+        client.encode(EgressECIESValue::Auth, &mut buf).unwrap();
+        assert!(matches!(
+            server.decode(&mut buf).unwrap(),
+            Some(IngressECIESValue::AuthReceive(_))
+        ));
+        server.encode(EgressECIESValue::Ack, &mut buf).unwrap();
+        assert_eq!(
+            client.decode(&mut buf).unwrap(),
+            Some(IngressECIESValue::Ack)
+        );
+        assert!(buf.is_empty());
+
+        (client, server)
+    }
+
+    #[test]
+    fn handshake_then_message() {
+        let (mut client, mut server) = connected_pair();
+
+        let mut buf = BytesMut::new();
+        client
+            .encode(
+                EgressECIESValue::Message(Bytes::from_static(b"hello")),
+                &mut buf,
+            )
+            .unwrap();
+        assert_eq!(
+            server.decode(&mut buf).unwrap(),
+            Some(IngressECIESValue::Message(Bytes::from_static(b"hello")))
+        );
+    }
+
+    #[test]
+    fn decodes_queued_frames_one_at_a_time() {
+        let (mut client, mut server) = connected_pair();
+
+        let messages = [
+            Bytes::from_static(b"first"),
+            Bytes::from(vec![0xab; 48]),
+            Bytes::from_static(b"third frame, not block aligned"),
+        ];
+
+        let mut buf = BytesMut::new();
+        for message in &messages {
+            server
+                .encode(EgressECIESValue::Message(message.clone()), &mut buf)
+                .unwrap();
+        }
+
+        for message in messages {
+            assert_eq!(
+                client.decode(&mut buf).unwrap(),
+                Some(IngressECIESValue::Message(message))
+            );
+        }
+        assert!(buf.is_empty());
+        assert_eq!(client.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_oversized_auth_prefix_before_buffering() {
+        let mut server = ECIESCodec::new_server(SecretKey::new(&mut thread_rng())).unwrap();
+
+        let mut buf = BytesMut::from(&[0xff, 0xff, 0x00][..]);
+        assert!(matches!(
+            server.decode(&mut buf),
+            Err(ECIESEerror::InvalidAuthData)
+        ));
+    }
+
+    #[test]
+    fn short_write_resumes_without_reencoding() {
+        let (mut client, mut server) = connected_pair();
+
+        let mut outgoing = BytesMut::new();
+        client
+            .encode(
+                EgressECIESValue::Message(Bytes::from_static(b"interrupted")),
+                &mut outgoing,
+            )
+            .unwrap();
+
+        // The socket accepts only part of the frame, then a second message is queued behind
+        // the unwritten remainder before the write is resumed.
+        let mut wire = outgoing.split_to(10);
+        assert_eq!(server.decode(&mut wire).unwrap(), None);
+        client
+            .encode(
+                EgressECIESValue::Message(Bytes::from_static(b"next")),
+                &mut outgoing,
+            )
+            .unwrap();
+        wire.unsplit(outgoing);
+
+        assert_eq!(
+            server.decode(&mut wire).unwrap(),
+            Some(IngressECIESValue::Message(Bytes::from_static(
+                b"interrupted"
+            )))
+        );
+        assert_eq!(
+            server.decode(&mut wire).unwrap(),
+            Some(IngressECIESValue::Message(Bytes::from_static(b"next")))
+        );
+    }
+
+    #[test]
+    fn rejects_inbound_frame_over_limit() {
+        let (mut client, mut server) = connected_pair();
+        server.set_max_inbound_frame(16);
+
+        let mut buf = BytesMut::new();
+        client
+            .encode(
+                EgressECIESValue::Message(Bytes::from(vec![0; 17])),
+                &mut buf,
+            )
+            .unwrap();
+        assert!(matches!(
+            server.decode(&mut buf),
+            Err(ECIESEerror::OversizedFrame { size: 17, max: 16 })
+        ));
+    }
+
+    #[test]
+    fn refuses_outbound_message_over_limit() {
+        let (mut client, mut server) = connected_pair();
+        client.set_max_outbound_frame(16);
+
+        let mut buf = BytesMut::new();
+        assert!(matches!(
+            client.encode(
+                EgressECIESValue::Message(Bytes::from(vec![0; 17])),
+                &mut buf
+            ),
+            Err(ECIESEerror::OversizedFrame { size: 17, max: 16 })
+        ));
+        assert!(buf.is_empty());
+
+        // Nothing was consumed from the egress stream, so later frames still decode.
+        client
+            .encode(
+                EgressECIESValue::Message(Bytes::from(vec![0; 16])),
+                &mut buf,
+            )
+            .unwrap();
+        assert_eq!(
+            server.decode(&mut buf).unwrap(),
+            Some(IngressECIESValue::Message(Bytes::from(vec![0; 16])))
+        );
+    }
+
+    #[test]
+    fn waits_for_partial_frame() {
+        let (mut client, mut server) = connected_pair();
+
+        let mut buf = BytesMut::new();
+        server
+            .encode(
+                EgressECIESValue::Message(Bytes::from_static(b"split")),
+                &mut buf,
+            )
+            .unwrap();
+        let mut rest = buf.split_off(Secrets::header_len() + 4);
+
+        // The header is verified but left in the buffer until the body arrives.
+        assert_eq!(client.decode(&mut buf).unwrap(), None);
+        assert_eq!(client.decode(&mut buf).unwrap(), None);
+        assert_eq!(client.state(), ECIESState::Header);
+        assert_eq!(buf.len(), Secrets::header_len() + 4);
+
+        buf.unsplit(rest.split());
+        assert_eq!(
+            client.decode(&mut buf).unwrap(),
+            Some(IngressECIESValue::Message(Bytes::from_static(b"split")))
+        );
+    }
+
+    #[test]
+    fn rejects_frame_with_corrupted_body() {
+        let (mut client, mut server) = connected_pair();
+
+        let mut buf = BytesMut::new();
+        client
+            .encode(
+                EgressECIESValue::Message(Bytes::from_static(b"hello")),
+                &mut buf,
+            )
+            .unwrap();
+        // The header and its MAC are intact; only the ciphertext after them is altered.
+        buf[Secrets::header_len()] ^= 1;
+
+        assert!(matches!(
+            server.decode(&mut buf),
+            Err(ECIESEerror::BodyMacMismatch)
+        ));
+    }
+
+    #[test]
+    fn shared_tracker_rejects_reused_ack_nonce() {
+        let server_secret_key = SecretKey::new(&mut thread_rng());
+        let server_id = pk2id(&PublicKey::from_secret_key(SECP256K1, &server_secret_key));
+        let tracker = Arc::new(Mutex::new(NonceTracker::new(16)));
+
+        let handshake = || {
+            let mut client =
+                ECIESCodec::new_client(SecretKey::new(&mut thread_rng()), server_id).unwrap();
+            let mut server = ECIESCodec::new_server(server_secret_key).unwrap();
+            server.ecies = ECIES::new_static_server(
+                server_secret_key,
+                H256::repeat_byte(0x07),
+                SecretKey::new(&mut thread_rng()),
+            )
+            .unwrap();
+            server.set_nonce_tracker(tracker.clone());
+
+            let mut buf = BytesMut::new();
+            client.encode(EgressECIESValue::Auth, &mut buf).unwrap();
+            server.decode(&mut buf).unwrap();
+            server.encode(EgressECIESValue::Ack, &mut buf).map(|_| buf)
+        };
+
+        assert!(!handshake().unwrap().is_empty());
+        assert!(matches!(handshake(), Err(ECIESEerror::NonceReused)));
+    }
+
+    #[test]
+    fn shared_cache_rejects_replayed_auth() {
+        let server_secret_key = SecretKey::new(&mut thread_rng());
+        let server_id = pk2id(&PublicKey::from_secret_key(SECP256K1, &server_secret_key));
+        let cache = Arc::new(Mutex::new(AuthReplayCache::new(16)));
+
+        let mut auth = BytesMut::new();
+        ECIESCodec::new_client(SecretKey::new(&mut thread_rng()), server_id)
+            .unwrap()
+            .encode(EgressECIESValue::Auth, &mut auth)
+            .unwrap();
+        let receive = |mut buf: BytesMut| {
+            let mut server = ECIESCodec::new_server(server_secret_key).unwrap();
+            server.set_auth_replay_cache(cache.clone());
+            server.decode(&mut buf)
+        };
+
+        assert!(matches!(
+            receive(auth.clone()),
+            Ok(Some(IngressECIESValue::AuthReceive(_)))
+        ));
+        assert!(matches!(receive(auth), Err(ECIESEerror::InvalidAuthData)));
+    }
+
+    #[test]
+    fn rejects_frame_with_corrupted_header() {
+        let (mut client, mut server) = connected_pair();
+
+        let mut buf = BytesMut::new();
+        client
+            .encode(
+                EgressECIESValue::Message(Bytes::from_static(b"hello")),
+                &mut buf,
+            )
+            .unwrap();
+        buf[0] ^= 1;
+
+        assert!(matches!(
+            server.decode(&mut buf),
+            Err(ECIESEerror::HeaderMacMismatch)
+        ));
+    }
+}
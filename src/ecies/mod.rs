@@ -1,3 +1,11 @@
 mod algorithm;
+mod codec;
+mod nonce;
+mod secrets;
+mod suite;
 
 pub use algorithm::*;
+pub use codec::*;
+pub use nonce::*;
+pub use secrets::*;
+pub use suite::*;
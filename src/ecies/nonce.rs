@@ -0,0 +1,146 @@
+use crate::{errors::ECIESEerror, util::keccak256};
+use ethereum_types::H256;
+use std::{
+    collections::{HashSet, VecDeque},
+    hash::Hash,
+};
+
+/// The last `capacity` distinct values inserted; the oldest is forgotten to make room.
+#[derive(Debug)]
+struct BoundedSet<T> {
+    capacity: usize,
+    order: VecDeque<T>,
+    seen: HashSet<T>,
+}
+
+impl<T: Copy + Eq + Hash> BoundedSet<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::with_capacity(capacity),
+            seen: HashSet::with_capacity(capacity),
+        }
+    }
+
+    /// Records `value`, returning `false` if it is still remembered.
+    fn insert(&mut self, value: T) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if !self.seen.insert(value) {
+            return false;
+        }
+        if self.order.len() == self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.seen.remove(&oldest);
+        }
+        self.order.push_back(value);
+        true
+    }
+}
+
+/// Remembers the most recent handshake nonces so that a reused one, e.g. from a broken RNG or a
+/// reused `ECIES` instance, is caught before it weakens the derived secrets. Shared by all
+/// handshakes of a node; the oldest nonce is forgotten once `capacity` is reached.
+#[derive(Debug)]
+pub struct NonceTracker(BoundedSet<H256>);
+
+impl NonceTracker {
+    pub fn new(capacity: usize) -> Self {
+        Self(BoundedSet::new(capacity))
+    }
+
+    /// Records `nonce`, failing with `NonceReused` if it is still remembered.
+    pub fn insert(&mut self, nonce: H256) -> Result<(), ECIESEerror> {
+        if !self.0.insert(nonce) {
+            return Err(ECIESEerror::NonceReused);
+        }
+        Ok(())
+    }
+}
+
+/// Remembers the hashes of recently received `auth` packets, so that a replayed packet cannot
+/// start a second session. Shared by all handshakes of a node, like [`NonceTracker`].
+#[derive(Debug)]
+pub struct AuthReplayCache(BoundedSet<H256>);
+
+impl AuthReplayCache {
+    pub fn new(capacity: usize) -> Self {
+        Self(BoundedSet::new(capacity))
+    }
+
+    /// Records `auth`, failing with `InvalidAuthData` if the same packet is still remembered.
+    pub fn insert(&mut self, auth: &[u8]) -> Result<(), ECIESEerror> {
+        if !self.0.insert(keccak256(auth)) {
+            return Err(ECIESEerror::InvalidAuthData);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecies::ECIES;
+    use crate::util::pk2id;
+    use rand::thread_rng;
+    use secp256k1::{PublicKey, SecretKey, SECP256K1};
+
+    #[test]
+    fn reused_nonce_is_flagged() {
+        let nonce = H256::repeat_byte(0x07);
+        let new = || {
+            ECIES::new_static_server(
+                SecretKey::new(&mut thread_rng()),
+                nonce,
+                SecretKey::new(&mut thread_rng()),
+            )
+            .unwrap()
+        };
+        let mut tracker = NonceTracker::new(16);
+
+        new().claim_nonce(&mut tracker).unwrap();
+        assert!(matches!(
+            new().claim_nonce(&mut tracker),
+            Err(ECIESEerror::NonceReused)
+        ));
+        let fresh = ECIES::new_server(SecretKey::new(&mut thread_rng())).unwrap();
+        fresh.claim_nonce(&mut tracker).unwrap();
+    }
+
+    #[test]
+    fn forgets_oldest_nonce_at_capacity() {
+        let mut tracker = NonceTracker::new(2);
+        for byte in 1..=3 {
+            tracker.insert(H256::repeat_byte(byte)).unwrap();
+        }
+        tracker.insert(H256::repeat_byte(1)).unwrap();
+        assert!(tracker.insert(H256::repeat_byte(3)).is_err());
+    }
+
+    #[test]
+    fn replayed_auth_is_rejected() {
+        let server_key = SecretKey::new(&mut thread_rng());
+        let server_id = pk2id(&PublicKey::from_secret_key(SECP256K1, &server_key));
+        let auth = || {
+            ECIES::new_client(SecretKey::new(&mut thread_rng()), server_id)
+                .unwrap()
+                .create_auth()
+                .unwrap()
+        };
+        let receive = |auth: &[u8], cache: &mut AuthReplayCache| {
+            let mut server = ECIES::new_server(server_key).unwrap();
+            server.parse_auth(&mut auth.to_vec()).unwrap();
+            server.claim_auth(cache)
+        };
+        let mut cache = AuthReplayCache::new(16);
+
+        let replayed = auth();
+        receive(&replayed, &mut cache).unwrap();
+        assert!(matches!(
+            receive(&replayed, &mut cache),
+            Err(ECIESEerror::InvalidAuthData)
+        ));
+        receive(&auth(), &mut cache).unwrap();
+    }
+}
@@ -0,0 +1,655 @@
+use super::{algorithm::split_at_mut, FrameCipher, FRAME_KEY_LEN, FRAME_MAC_LEN};
+use crate::{
+    errors::ECIESEerror,
+    mac::{header_bytes_from, header_to_array, HeaderBytes, MAC},
+    util::keccak256,
+};
+use aes::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use bytes::{Buf, Bytes, BytesMut};
+use educe::Educe;
+use ethereum_types::{H128, H256};
+use rlp::Rlp;
+
+/// Largest body a frame header can announce, as its size field is three bytes.
+pub const MAX_FRAME_SIZE: usize = (1 << 24) - 1;
+
+/// Room for header-data in a frame header, after the three-byte body size.
+pub const HEADER_DATA_LEN: usize = 13;
+
+/// Header-data `[capability-id, context-id]`, both zero, for frames that are not part of a
+/// chunked message.
+pub const DEFAULT_HEADER_DATA: [u8; 3] = [0xc2, 0x80, 0x80];
+
+/// Bytes each direction may encrypt before the connection has to be dropped. The 128-bit CTR
+/// counter cannot wrap before this, so keystream is never reused, and every position up to it
+/// fits the `u64` that [`Secrets::to_bytes`] stores.
+pub const MAX_KEYSTREAM_BYTES: u128 = u64::MAX as u128;
+
+/// Fails if encrypting `len` more bytes with `cipher` would pass [`MAX_KEYSTREAM_BYTES`].
+fn reserve_keystream(cipher: &FrameCipher, len: usize) -> Result<(), ECIESEerror> {
+    if cipher.current_pos::<u128>() + len as u128 > MAX_KEYSTREAM_BYTES {
+        return Err(ECIESEerror::KeystreamExhausted);
+    }
+    Ok(())
+}
+
+/// Fails with `OversizedFrame` if a `size` byte body does not fit the header's size field.
+fn check_frame_size(size: usize) -> Result<(), ECIESEerror> {
+    if size > MAX_FRAME_SIZE {
+        return Err(ECIESEerror::OversizedFrame {
+            size,
+            max: MAX_FRAME_SIZE,
+        });
+    }
+    Ok(())
+}
+
+/// Decrypted contents of a frame header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub body_size: usize,
+    /// The RLP header-data list, without the zero padding that follows it.
+    pub header_data: Bytes,
+}
+
+/// A frame decoded by [`Secrets::decode_frame`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub header_data: Bytes,
+    pub body: Bytes,
+}
+
+/// Cuts the padding off header-data. Anything that is not a single RLP item fitting in the
+/// header is returned whole, for the caller's decoder to reject.
+fn trim_header_data(data: &[u8]) -> &[u8] {
+    let len = Rlp::new(data)
+        .payload_info()
+        .ok()
+        .and_then(|info| info.header_len.checked_add(info.value_len));
+    match len {
+        Some(len) if len <= data.len() => &data[..len],
+        _ => data,
+    }
+}
+
+/// Frame encryption state derived at the end of the handshake.
+///
+/// Both AES-CTR streams and both MACs run continuously for the lifetime of the connection,
+/// so frames must be written and read strictly in order.
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub struct Secrets {
+    #[educe(Debug(ignore))]
+    aes_secret: H256,
+
+    #[educe(Debug(ignore))]
+    ingress_aes: FrameCipher,
+    #[educe(Debug(ignore))]
+    egress_aes: FrameCipher,
+    #[educe(Debug(ignore))]
+    ingress_mac: MAC,
+    #[educe(Debug(ignore))]
+    egress_mac: MAC,
+
+    body_size: Option<usize>,
+}
+
+impl Secrets {
+    /// Length of the output of [`Secrets::to_bytes`].
+    pub const SERIALIZED_LEN: usize = FRAME_KEY_LEN + 8 + 8 + 2 * MAC::SERIALIZED_LEN + 1 + 8;
+
+    pub(crate) fn new(aes_secret: H256, ingress_mac: MAC, egress_mac: MAC) -> Self {
+        let iv = H128::default();
+        Self {
+            aes_secret,
+            ingress_aes: FrameCipher::new(aes_secret.as_ref().into(), iv.as_ref().into()),
+            egress_aes: FrameCipher::new(aes_secret.as_ref().into(), iv.as_ref().into()),
+            ingress_mac,
+            egress_mac,
+            body_size: None,
+        }
+    }
+
+    /// Exports the complete frame state, so that framing can be resumed by
+    /// [`Secrets::from_bytes`] in another process or behind an FFI boundary.
+    ///
+    /// The output contains the AES secret, both keystream positions and the full MAC states,
+    /// including the MAC secret. It must be treated as key material.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(Self::SERIALIZED_LEN);
+        out.extend_from_slice(self.aes_secret.as_bytes());
+        out.extend_from_slice(&self.ingress_aes.current_pos::<u64>().to_be_bytes());
+        out.extend_from_slice(&self.egress_aes.current_pos::<u64>().to_be_bytes());
+        out.extend_from_slice(&self.ingress_mac.to_bytes());
+        out.extend_from_slice(&self.egress_mac.to_bytes());
+        out.push(self.body_size.is_some() as u8);
+        out.extend_from_slice(&(self.body_size.unwrap_or_default() as u64).to_be_bytes());
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ECIESEerror> {
+        let invalid = || ECIESEerror::Other(anyhow::anyhow!("invalid serialized secrets"));
+        if data.len() != Self::SERIALIZED_LEN {
+            return Err(invalid());
+        }
+
+        let (aes_secret, rest) = data.split_at(FRAME_KEY_LEN);
+        let (ingress_pos, rest) = rest.split_at(8);
+        let (egress_pos, rest) = rest.split_at(8);
+        let (ingress_mac, rest) = rest.split_at(MAC::SERIALIZED_LEN);
+        let (egress_mac, rest) = rest.split_at(MAC::SERIALIZED_LEN);
+        let (has_body_size, body_size) = rest.split_at(1);
+
+        let mut secrets = Self::new(
+            H256::from_slice(aes_secret),
+            MAC::from_bytes(ingress_mac).ok_or_else(invalid)?,
+            MAC::from_bytes(egress_mac).ok_or_else(invalid)?,
+        );
+        secrets
+            .ingress_aes
+            .try_seek(u64::from_be_bytes(ingress_pos.try_into().unwrap()))
+            .map_err(|_| invalid())?;
+        secrets
+            .egress_aes
+            .try_seek(u64::from_be_bytes(egress_pos.try_into().unwrap()))
+            .map_err(|_| invalid())?;
+        secrets.body_size = match has_body_size[0] {
+            0 => None,
+            // No header can announce more, and a larger value would overflow `body_len`.
+            1 => Some(
+                usize::try_from(u64::from_be_bytes(body_size.try_into().unwrap()))
+                    .ok()
+                    .filter(|&size| size <= MAX_FRAME_SIZE)
+                    .ok_or_else(invalid)?,
+            ),
+            _ => return Err(invalid()),
+        };
+
+        Ok(secrets)
+    }
+
+    /// Frame state for the given secrets with fresh keystreams, as both ends have right after
+    /// the handshake apart from the MAC seeds. For tests and fuzzing only.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn from_keys(aes_secret: H256, mac_secret: H256) -> Self {
+        Self::new(aes_secret, MAC::new(mac_secret), MAC::new(mac_secret))
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn aes_secret(&self) -> H256 {
+        self.aes_secret
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn mac_secret(&self) -> H256 {
+        self.egress_mac.secret()
+    }
+
+    /// MAC state for frames we receive.
+    pub fn ingress_mac(&self) -> &MAC {
+        &self.ingress_mac
+    }
+
+    /// MAC state for frames we send.
+    pub fn egress_mac(&self) -> &MAC {
+        &self.egress_mac
+    }
+
+    /// Identifier of this session, `keccak256(aes-secret || mac-secret)`, the same on both ends.
+    /// It cannot be turned back into the secrets or the peer id, so it is safe to log for
+    /// correlating events across a connection.
+    pub fn session_id(&self) -> H256 {
+        keccak256(
+            &[
+                self.aes_secret.as_bytes(),
+                self.egress_mac.secret().as_bytes(),
+            ]
+            .concat(),
+        )
+    }
+
+    /// Encrypts `body` into a complete frame, header and MACs included, with
+    /// [`DEFAULT_HEADER_DATA`].
+    pub fn encode_frame(&mut self, body: &[u8]) -> Result<Vec<u8>, ECIESEerror> {
+        self.encode_frame_with_header_data(&DEFAULT_HEADER_DATA, body)
+    }
+
+    /// [`Secrets::encode_frame`] with the given header-data, e.g. a
+    /// [`Chunk::header_data`](crate::transport::Chunk::header_data).
+    pub fn encode_frame_with_header_data(
+        &mut self,
+        header_data: &[u8],
+        body: &[u8],
+    ) -> Result<Vec<u8>, ECIESEerror> {
+        check_frame_size(body.len())?;
+        // Check the whole frame up front so a failure never leaves half a frame behind.
+        reserve_keystream(&self.egress_aes, 16 + body.len().next_multiple_of(16))?;
+
+        let mut out = BytesMut::new();
+        self.write_header(&mut out, body.len(), header_data)?;
+        self.write_body(&mut out, body)?;
+        Ok(out.to_vec())
+    }
+
+    /// Decodes the first frame in `input`, returning it and the number of bytes it took up.
+    /// Returns `None` without touching any state while the frame is incomplete, so the call
+    /// can be repeated once more input has arrived.
+    pub fn decode_frame(&mut self, input: &[u8]) -> Result<Option<(Frame, usize)>, ECIESEerror> {
+        self.decode_frame_with_limit(input, MAX_FRAME_SIZE)
+    }
+
+    /// [`Secrets::decode_frame`], failing with `OversizedFrame` as soon as a header announces
+    /// a body larger than `max`. The body is copied out of `input` once and decrypted there.
+    pub(crate) fn decode_frame_with_limit(
+        &mut self,
+        input: &[u8],
+        max: usize,
+    ) -> Result<Option<(Frame, usize)>, ECIESEerror> {
+        self.with_ingress_rollback(|secrets| {
+            let Some((header, frame_len)) = secrets.read_frame_header(input, max)? else {
+                return Ok(None);
+            };
+            let mut body = BytesMut::from(&input[Self::header_len()..frame_len]);
+            let size = secrets.read_body(&mut body)?.len();
+            body.truncate(size);
+            let frame = Frame {
+                header_data: header.header_data,
+                body: body.freeze(),
+            };
+            Ok(Some((frame, frame_len)))
+        })
+    }
+
+    /// Decodes the first frame in `buf` like [`Secrets::decode_frame_with_limit`], but
+    /// decrypts the body where it lies and splits it off `buf` without copying. `buf` is left
+    /// untouched while the frame is incomplete.
+    pub(crate) fn decode_frame_in_place(
+        &mut self,
+        buf: &mut BytesMut,
+        max: usize,
+    ) -> Result<Option<Frame>, ECIESEerror> {
+        self.with_ingress_rollback(|secrets| {
+            let Some((header, frame_len)) = secrets.read_frame_header(buf, max)? else {
+                return Ok(None);
+            };
+            let size = secrets
+                .read_body(&mut buf[Self::header_len()..frame_len])?
+                .len();
+            let mut body = buf.split_to(frame_len);
+            body.advance(Self::header_len());
+            body.truncate(size);
+            Ok(Some(Frame {
+                header_data: header.header_data,
+                body: body.freeze(),
+            }))
+        })
+    }
+
+    /// Reads the header at the start of `input`, returning it with the length of the whole
+    /// frame, or `None` if the frame has not fully arrived yet.
+    fn read_frame_header(
+        &mut self,
+        input: &[u8],
+        max: usize,
+    ) -> Result<Option<(FrameHeader, usize)>, ECIESEerror> {
+        if input.len() < Self::header_len() {
+            return Ok(None);
+        }
+        let header = self.read_header(&input[..Self::header_len()])?;
+        if header.body_size > max {
+            return Err(ECIESEerror::OversizedFrame {
+                size: header.body_size,
+                max,
+            });
+        }
+        let frame_len = Self::header_len() + self.body_len().unwrap();
+        if input.len() < frame_len {
+            return Ok(None);
+        }
+        Ok(Some((header, frame_len)))
+    }
+
+    /// Runs `decode`, restoring the ingress cipher and MAC unless it returns a frame, so that
+    /// nothing is committed until the whole frame has arrived. The header is still verified
+    /// right away.
+    fn with_ingress_rollback<T>(
+        &mut self,
+        decode: impl FnOnce(&mut Self) -> Result<Option<T>, ECIESEerror>,
+    ) -> Result<Option<T>, ECIESEerror> {
+        let ingress_aes = self.ingress_aes.clone();
+        let ingress_mac = self.ingress_mac.clone();
+        let body_size = self.body_size;
+
+        let result = decode(self);
+        if !matches!(result, Ok(Some(_))) {
+            self.ingress_aes = ingress_aes;
+            self.ingress_mac = ingress_mac;
+            self.body_size = body_size;
+        }
+        result
+    }
+
+    pub const fn header_len() -> usize {
+        16 + FRAME_MAC_LEN
+    }
+
+    /// Length of the padded body plus its MAC, as announced by the last header read. `None`
+    /// unless a header has been read and its body not yet.
+    pub fn body_len(&self) -> Option<usize> {
+        self.body_size
+            .map(|size| size.next_multiple_of(16) + FRAME_MAC_LEN)
+    }
+
+    /// Writes the header of a frame with a `size` byte body. `size` must fit the three-byte
+    /// size field, i.e. not exceed [`MAX_FRAME_SIZE`], and `header_data` must fit in
+    /// [`HEADER_DATA_LEN`] bytes and is zero padded.
+    pub fn write_header(
+        &mut self,
+        out: &mut BytesMut,
+        size: usize,
+        header_data: &[u8],
+    ) -> Result<(), ECIESEerror> {
+        check_frame_size(size)?;
+        if header_data.len() > HEADER_DATA_LEN {
+            return Err(ECIESEerror::InvalidHeader);
+        }
+        reserve_keystream(&self.egress_aes, 16)?;
+        let mut header = HeaderBytes::default();
+        header[..3].copy_from_slice(&(size as u64).to_be_bytes()[5..]);
+        header[3..3 + header_data.len()].copy_from_slice(header_data);
+
+        self.egress_aes.apply_keystream(&mut header);
+        self.egress_mac.update_header(&header);
+        let tag = self.egress_mac.digest();
+
+        out.reserve(Self::header_len());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(tag.as_bytes());
+        Ok(())
+    }
+
+    /// Verifies and decrypts a frame header, returning the size of the body that follows and
+    /// the header-data.
+    pub fn read_header(&mut self, data: &[u8]) -> Result<FrameHeader, ECIESEerror> {
+        if data.len() != Self::header_len() {
+            return Err(ECIESEerror::InvalidHeader);
+        }
+        reserve_keystream(&self.ingress_aes, 16)?;
+        let mut header = header_bytes_from(&data[..16])?;
+        let mac = H128::from_slice(&data[16..]);
+
+        self.ingress_mac.update_header(&header);
+        let check_mac = self.ingress_mac.digest();
+        if check_mac != mac {
+            return Err(ECIESEerror::HeaderMacMismatch);
+        }
+
+        self.ingress_aes.apply_keystream(&mut header);
+        let header = header_to_array(&header);
+        let body_size = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        self.body_size = Some(body_size);
+
+        Ok(FrameHeader {
+            body_size,
+            header_data: Bytes::copy_from_slice(trim_header_data(&header[3..])),
+        })
+    }
+
+    pub fn write_body(&mut self, out: &mut BytesMut, data: &[u8]) -> Result<(), ECIESEerror> {
+        let len = data.len().next_multiple_of(16);
+        reserve_keystream(&self.egress_aes, len)?;
+        let old_len = out.len();
+        out.resize(old_len + len, 0);
+
+        let encrypted = &mut out[old_len..old_len + len];
+        encrypted[..data.len()].copy_from_slice(data);
+
+        self.egress_aes.apply_keystream(encrypted);
+        self.egress_mac.update_body(encrypted);
+        let tag = self.egress_mac.digest();
+
+        out.extend_from_slice(tag.as_bytes());
+        Ok(())
+    }
+
+    /// Verifies and decrypts a frame body of [`Self::body_len`] bytes, returning the payload
+    /// without padding.
+    pub fn read_body<'a>(&mut self, data: &'a mut [u8]) -> Result<&'a mut [u8], ECIESEerror> {
+        let size = self.body_size.ok_or(ECIESEerror::InvalidHeader)?;
+        let mac_index = data
+            .len()
+            .checked_sub(FRAME_MAC_LEN)
+            .ok_or(ECIESEerror::InvalidHeader)?;
+        let (body, mac_bytes) = split_at_mut(data, mac_index)?;
+        reserve_keystream(&self.ingress_aes, body.len())?;
+        let mac = H128::from_slice(mac_bytes);
+
+        self.ingress_mac.update_body(body);
+        let check_mac = self.ingress_mac.digest();
+        if check_mac != mac {
+            return Err(ECIESEerror::BodyMacMismatch);
+        }
+
+        self.body_size = None;
+        self.ingress_aes.apply_keystream(body);
+        Ok(split_at_mut(body, size)?.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two frame states sharing keys, as the two ends of a connection would.
+    fn pair() -> (Secrets, Secrets) {
+        let new = || Secrets::from_keys(H256::repeat_byte(0x11), H256::repeat_byte(0x22));
+        (new(), new())
+    }
+
+    fn read_frame(secrets: &mut Secrets, buf: &mut BytesMut) -> Vec<u8> {
+        let header = buf.split_to(Secrets::header_len());
+        secrets.read_header(&header).unwrap();
+        let mut body = buf.split_to(secrets.body_len().unwrap());
+        secrets.read_body(&mut body).unwrap().to_vec()
+    }
+
+    #[test]
+    fn serialized_secrets_resume_framing() {
+        let (mut egress, ingress) = pair();
+
+        let mut buf = BytesMut::new();
+        egress
+            .write_header(&mut buf, 5, &DEFAULT_HEADER_DATA)
+            .unwrap();
+        egress.write_body(&mut buf, b"first").unwrap();
+        let mut ingress = Secrets::from_bytes(&ingress.to_bytes()).unwrap();
+        assert_eq!(read_frame(&mut ingress, &mut buf), b"first");
+
+        // Hand both ends over mid-session and keep going.
+        let mut egress = Secrets::from_bytes(&egress.to_bytes()).unwrap();
+        let mut ingress = Secrets::from_bytes(&ingress.to_bytes()).unwrap();
+        egress
+            .write_header(&mut buf, 6, &DEFAULT_HEADER_DATA)
+            .unwrap();
+        egress.write_body(&mut buf, b"second").unwrap();
+        assert_eq!(read_frame(&mut ingress, &mut buf), b"second");
+
+        assert!(Secrets::from_bytes(&[0; 16]).is_err());
+    }
+
+    #[test]
+    fn rejects_serialized_body_size_beyond_frame_limit() {
+        let (secrets, _) = pair();
+        assert_eq!(secrets.body_len(), None);
+        let mut state = secrets.to_bytes();
+        let len = state.len();
+        state[len - 9] = 1;
+        state[len - 8..].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(Secrets::from_bytes(&state).is_err());
+
+        state[len - 8..].copy_from_slice(&(MAX_FRAME_SIZE as u64).to_be_bytes());
+        let restored = Secrets::from_bytes(&state).unwrap();
+        assert_eq!(
+            restored.body_len(),
+            Some(MAX_FRAME_SIZE.next_multiple_of(16) + FRAME_MAC_LEN)
+        );
+    }
+
+    #[test]
+    fn frame_roundtrip_through_byte_slices() {
+        let (mut egress, mut ingress) = pair();
+
+        let mut wire = egress.encode_frame(b"first").unwrap();
+        wire.extend(egress.encode_frame(&[0xab; 40]).unwrap());
+        wire.extend(egress.encode_frame(b"").unwrap());
+
+        // Incomplete input leaves the state untouched.
+        assert_eq!(ingress.decode_frame(&wire[..40]).unwrap(), None);
+
+        let mut decode = |input: &[u8]| {
+            let (frame, consumed) = ingress.decode_frame(input).unwrap().unwrap();
+            assert_eq!(frame.header_data.as_ref(), DEFAULT_HEADER_DATA);
+            (frame.body.to_vec(), consumed)
+        };
+        assert_eq!(decode(&wire), (b"first".to_vec(), 64));
+        assert_eq!(decode(&wire[64..]), (vec![0xab; 40], 96));
+        assert_eq!(decode(&wire[160..]), (vec![], 48));
+        assert_eq!(wire.len(), 208);
+    }
+
+    #[test]
+    fn in_place_decode_rolls_back_on_corrupted_body() {
+        let (mut egress, mut ingress) = pair();
+
+        let mut buf = BytesMut::from(&egress.encode_frame(b"in place").unwrap()[..]);
+        buf.extend_from_slice(&egress.encode_frame(b"next").unwrap());
+        let first_len = buf.len() - 64;
+
+        buf[Secrets::header_len()] ^= 1;
+        let corrupted = buf.clone();
+        assert!(matches!(
+            ingress.decode_frame_in_place(&mut buf, MAX_FRAME_SIZE),
+            Err(ECIESEerror::BodyMacMismatch)
+        ));
+        assert_eq!(buf, corrupted);
+        assert_eq!(ingress.body_len(), None);
+
+        buf[Secrets::header_len()] ^= 1;
+        let frame = ingress
+            .decode_frame_in_place(&mut buf, MAX_FRAME_SIZE)
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.body.as_ref(), b"in place");
+        assert_eq!(buf.len(), corrupted.len() - first_len);
+        let frame = ingress
+            .decode_frame_in_place(&mut buf, MAX_FRAME_SIZE)
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.body.as_ref(), b"next");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn write_header_rejects_size_beyond_size_field() {
+        let (mut egress, mut ingress) = pair();
+
+        let mut buf = BytesMut::new();
+        assert!(matches!(
+            egress.write_header(&mut buf, MAX_FRAME_SIZE + 1, &DEFAULT_HEADER_DATA),
+            Err(ECIESEerror::OversizedFrame { size, max: MAX_FRAME_SIZE })
+                if size == MAX_FRAME_SIZE + 1
+        ));
+        assert!(buf.is_empty());
+
+        // The egress stream was not advanced, so the next frame still decodes.
+        let wire = egress.encode_frame(b"after").unwrap();
+        let (frame, _) = ingress.decode_frame(&wire).unwrap().unwrap();
+        assert_eq!(frame.body.as_ref(), b"after");
+    }
+
+    #[test]
+    fn header_data_roundtrip() {
+        let (mut egress, mut ingress) = pair();
+
+        let header_data = [0xc5, 0x80, 0x07, 0x82, 0x04, 0x00];
+        let wire = egress
+            .encode_frame_with_header_data(&header_data, b"chunk")
+            .unwrap();
+        let (frame, _) = ingress.decode_frame(&wire).unwrap().unwrap();
+        assert_eq!(frame.header_data.as_ref(), header_data);
+        assert_eq!(frame.body.as_ref(), b"chunk");
+
+        assert!(matches!(
+            egress.encode_frame_with_header_data(&[0; HEADER_DATA_LEN + 1], b""),
+            Err(ECIESEerror::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn keystream_position_advances_and_is_capped() {
+        let (mut egress, _) = pair();
+
+        let mut buf = BytesMut::new();
+        egress
+            .write_header(&mut buf, 11, &DEFAULT_HEADER_DATA)
+            .unwrap();
+        egress.write_body(&mut buf, b"first frame").unwrap();
+        assert_eq!(egress.egress_aes.current_pos::<u128>(), 32);
+        egress
+            .write_header(&mut buf, 17, &DEFAULT_HEADER_DATA)
+            .unwrap();
+        egress.write_body(&mut buf, b"seventeen bytes!!").unwrap();
+        assert_eq!(egress.egress_aes.current_pos::<u128>(), 80);
+
+        egress.egress_aes.seek(MAX_KEYSTREAM_BYTES - 16);
+        let mut buf = BytesMut::new();
+        egress
+            .write_header(&mut buf, 1, &DEFAULT_HEADER_DATA)
+            .unwrap();
+        let header_only = buf.len();
+        assert!(matches!(
+            egress.write_body(&mut buf, b"x"),
+            Err(ECIESEerror::KeystreamExhausted)
+        ));
+        assert_eq!(buf.len(), header_only);
+
+        // The position reached at the cap still serializes.
+        assert_eq!(egress.egress_aes.current_pos::<u128>(), MAX_KEYSTREAM_BYTES);
+        let restored = Secrets::from_bytes(&egress.to_bytes()).unwrap();
+        assert_eq!(
+            restored.egress_aes.current_pos::<u128>(),
+            MAX_KEYSTREAM_BYTES
+        );
+    }
+
+    #[test]
+    fn keystream_continues_across_frames() {
+        let (mut egress, mut ingress) = pair();
+
+        let mut buf = BytesMut::new();
+        egress
+            .write_header(&mut buf, 11, &DEFAULT_HEADER_DATA)
+            .unwrap();
+        egress.write_body(&mut buf, b"first frame").unwrap();
+        let first_len = buf.len();
+        egress
+            .write_header(&mut buf, 12, &DEFAULT_HEADER_DATA)
+            .unwrap();
+        egress.write_body(&mut buf, b"second frame").unwrap();
+
+        // Decrypting the second body with a cipher restarted at counter zero must not yield
+        // the plaintext, otherwise the stream would be reusing keystream.
+        let mut second_body =
+            buf[first_len + Secrets::header_len()..buf.len() - FRAME_MAC_LEN].to_vec();
+        let mut restarted = FrameCipher::new(
+            H256::repeat_byte(0x11).as_ref().into(),
+            H128::default().as_ref().into(),
+        );
+        restarted.apply_keystream(&mut second_body);
+        assert_ne!(&second_body[..12], b"second frame");
+
+        assert_eq!(read_frame(&mut ingress, &mut buf), b"first frame");
+        assert_eq!(read_frame(&mut ingress, &mut buf), b"second frame");
+        assert!(buf.is_empty());
+    }
+}
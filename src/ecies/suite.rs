@@ -0,0 +1,45 @@
+//! The ciphers RLPx is built from, kept in one place so key and tag sizes are not repeated as
+//! magic numbers across the handshake, framing and MAC code.
+
+use aes::{
+    cipher::{typenum::Unsigned, BlockSizeUser, IvSizeUser, KeySizeUser},
+    Aes128, Aes256,
+};
+use ctr::{Ctr128BE, Ctr64BE};
+
+/// Stream cipher encrypting the `auth` and `ack` packets.
+pub type HandshakeCipher = Ctr64BE<Aes128>;
+/// Stream cipher encrypting frame headers and bodies.
+pub type FrameCipher = Ctr128BE<Aes256>;
+/// Block cipher the frame MAC encrypts its running digest with.
+pub type MacCipher = Aes256;
+
+/// Key length of [`HandshakeCipher`]; the other half of the derived key seeds the HMAC.
+pub const HANDSHAKE_KEY_LEN: usize = <HandshakeCipher as KeySizeUser>::KeySize::USIZE;
+/// Length of the HMAC-SHA256 tag ending each handshake packet.
+pub const HANDSHAKE_TAG_LEN: usize = 32;
+/// Length of the IV following the ephemeral public key in a handshake packet.
+pub const HANDSHAKE_IV_LEN: usize = <HandshakeCipher as IvSizeUser>::IvSize::USIZE;
+/// Bytes a handshake packet adds to its plaintext: ephemeral public key, IV and tag.
+pub const HANDSHAKE_OVERHEAD: usize = 65 + HANDSHAKE_IV_LEN + HANDSHAKE_TAG_LEN;
+/// Key length of [`FrameCipher`], i.e. the negotiated `aes-secret`.
+pub const FRAME_KEY_LEN: usize = <FrameCipher as KeySizeUser>::KeySize::USIZE;
+/// Key length of [`MacCipher`], i.e. the negotiated `mac-secret`.
+pub const MAC_KEY_LEN: usize = <MacCipher as KeySizeUser>::KeySize::USIZE;
+/// Length of the MAC following each frame header and body: one [`MacCipher`] block.
+pub const FRAME_MAC_LEN: usize = <MacCipher as BlockSizeUser>::BlockSize::USIZE;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_match_rlpx() {
+        assert_eq!(HANDSHAKE_KEY_LEN, 16);
+        assert_eq!(HANDSHAKE_IV_LEN, 16);
+        assert_eq!(HANDSHAKE_OVERHEAD, 113);
+        assert_eq!(FRAME_KEY_LEN, 32);
+        assert_eq!(MAC_KEY_LEN, 32);
+        assert_eq!(FRAME_MAC_LEN, 16);
+    }
+}
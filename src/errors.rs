@@ -9,19 +9,40 @@ pub enum ECIESEerror {
     #[error("tag check failure")]
     TagCheckFailed,
 
+    #[error("frame header MAC mismatch")]
+    HeaderMacMismatch,
+
+    #[error("frame body MAC mismatch")]
+    BodyMacMismatch,
+
     #[error("invalid auth data")]
     InvalidAuthData,
 
     #[error("invalid ack data")]
     InvalidAckData,
 
+    #[error("invalid header")]
+    InvalidHeader,
+
+    #[error("frame of {size} bytes exceeds the limit of {max} bytes")]
+    OversizedFrame { size: usize, max: usize },
+
+    #[error("unexpected handshake state")]
+    UnexpectedHandshakeState,
+
+    #[error("handshake nonce was already used")]
+    NonceReused,
+
+    #[error("frame keystream limit reached")]
+    KeystreamExhausted,
+
     #[error("other")]
     Other(#[from] anyhow::Error),
 }
 
 impl From<ECIESEerror> for io::Error {
     fn from(value: ECIESEerror) -> Self {
-        Self::new(io::ErrorKind::Other, format!("ECIES error: {:?}", value))
+        Self::other(format!("ECIES error: {:?}", value))
     }
 }
 
@@ -36,3 +57,18 @@ impl From<rlp::DecoderError> for ECIESEerror {
        Self::Other(value.into()) 
     }
 }
+
+impl From<snap::Error> for ECIESEerror {
+    fn from(value: snap::Error) -> Self {
+       Self::Other(value.into())
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ForkError {
+    #[error("remote node is stale and needs a software update")]
+    RemoteStale,
+
+    #[error("local node is on an incompatible chain or needs a software update")]
+    LocalIncompatibleOrStale,
+}
@@ -1,7 +1,13 @@
-mod errors;
-mod mac;
-mod ecies;
-mod types;
+pub mod errors;
+pub mod mac;
+pub mod ecies;
+pub mod types;
+pub mod subprotocols;
+pub mod util;
+pub mod compression;
+pub mod p2p;
+pub mod transport;
+pub mod crypto;
 
 
 pub fn add(left: usize, right: usize) -> usize {
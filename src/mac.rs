@@ -1,18 +1,118 @@
-use aes::*;
-// use block_modes::{block_padding::NoPadding, BlockMode, Ecb};
+use crate::{ecies::{MacCipher, MAC_KEY_LEN}, errors::ECIESEerror};
+use aes::cipher::{BlockEncrypt, KeyInit};
 use ethereum_types::{H128, H256};
 use generic_array::{typenum::U16, GenericArray};
-use sha3::{Digest, Keccak256};
 
 pub type HeaderBytes = GenericArray<u8, U16>;
 
-#[derive(Debug)]
+/// Copies a 16-byte frame header out of `slice`, failing with `InvalidHeader` on any other
+/// length.
+pub fn header_bytes_from(slice: &[u8]) -> Result<HeaderBytes, ECIESEerror> {
+    if slice.len() != 16 {
+        return Err(ECIESEerror::InvalidHeader);
+    }
+    Ok(HeaderBytes::clone_from_slice(slice))
+}
+
+/// Copies a frame header into a plain array, the inverse of [`header_bytes_from`].
+pub fn header_to_array(header: &HeaderBytes) -> [u8; 16] {
+    (*header).into()
+}
+
+const KECCAK_RATE: usize = 136;
+
+/// Keccak-256 sponge whose state can be exported, so that a MAC can be handed over mid-session.
+/// `sha3::Keccak256` keeps its state private.
+#[derive(Clone, Debug)]
+struct Keccak256 {
+    state: [u64; 25],
+    buffer: [u8; KECCAK_RATE],
+    buffered: usize,
+}
+
+impl Keccak256 {
+    const SERIALIZED_LEN: usize = 25 * 8 + 1 + KECCAK_RATE;
+
+    fn new() -> Self {
+        Self {
+            state: [0; 25],
+            buffer: [0; KECCAK_RATE],
+            buffered: 0,
+        }
+    }
+
+    fn absorb_buffer(&mut self) {
+        for (lane, chunk) in self.state.iter_mut().zip(self.buffer.chunks_exact(8)) {
+            *lane ^= u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        keccak::f1600(&mut self.state);
+        self.buffered = 0;
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let n = (KECCAK_RATE - self.buffered).min(data.len());
+            self.buffer[self.buffered..self.buffered + n].copy_from_slice(&data[..n]);
+            self.buffered += n;
+            data = &data[n..];
+            if self.buffered == KECCAK_RATE {
+                self.absorb_buffer();
+            }
+        }
+    }
+
+    fn finalize(mut self) -> [u8; 32] {
+        self.buffer[self.buffered..].fill(0);
+        self.buffer[self.buffered] ^= 0x01;
+        self.buffer[KECCAK_RATE - 1] ^= 0x80;
+        self.absorb_buffer();
+
+        let mut out = [0_u8; 32];
+        for (chunk, lane) in out.chunks_exact_mut(8).zip(self.state) {
+            chunk.copy_from_slice(&lane.to_le_bytes());
+        }
+        out
+    }
+
+    fn to_bytes(&self, out: &mut Vec<u8>) {
+        for lane in self.state {
+            out.extend_from_slice(&lane.to_le_bytes());
+        }
+        out.push(self.buffered as u8);
+        out.extend_from_slice(&self.buffer);
+    }
+
+    fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != Self::SERIALIZED_LEN {
+            return None;
+        }
+        let (lanes, rest) = data.split_at(25 * 8);
+        let mut state = [0_u64; 25];
+        for (lane, chunk) in state.iter_mut().zip(lanes.chunks_exact(8)) {
+            *lane = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        let buffered = rest[0] as usize;
+        if buffered >= KECCAK_RATE {
+            return None;
+        }
+        Some(Self {
+            state,
+            buffer: rest[1..].try_into().unwrap(),
+            buffered,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct MAC {
     secret: H256,
     hasher: Keccak256,
 }
 
 impl MAC {
+    /// Length of the output of [`MAC::to_bytes`].
+    pub const SERIALIZED_LEN: usize = MAC_KEY_LEN + Keccak256::SERIALIZED_LEN;
+
     pub fn new(secret: H256) -> Self {
         Self {
             secret,
@@ -20,15 +120,151 @@ impl MAC {
         }
     }
 
+    /// Creates a frame MAC with the standard RLPx seed `(mac_secret ^ nonce) || packet`.
+    pub fn seeded(mac_secret: H256, nonce: H256, packet: &[u8]) -> Self {
+        let mut mac = Self::new(mac_secret);
+        mac.update((mac_secret ^ nonce).as_bytes());
+        mac.update(packet);
+        mac
+    }
+
+    /// Exports the secret and the exact hasher state.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(Self::SERIALIZED_LEN);
+        out.extend_from_slice(self.secret.as_bytes());
+        self.hasher.to_bytes(&mut out);
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != Self::SERIALIZED_LEN {
+            return None;
+        }
+        Some(Self {
+            secret: H256::from_slice(&data[..MAC_KEY_LEN]),
+            hasher: Keccak256::from_bytes(&data[MAC_KEY_LEN..])?,
+        })
+    }
+
+    pub(crate) fn secret(&self) -> H256 {
+        self.secret
+    }
+
      pub fn update(&mut self, data: &[u8]) {
          self.hasher.update(data);
      }
 
-    // pub fn update_header(&mut self, data: &HeaderBytes) {
-    //     let aes = Ecb::<_, NoPadding>::new(Aes256::new_from_slice)
-    // }
+    /// Encrypts the current digest with the MAC secret (AES-256-ECB, single block).
+    fn encrypted_digest(&self) -> HeaderBytes {
+        let aes = MacCipher::new_from_slice(self.secret.as_ref()).unwrap();
+        let mut encrypted = HeaderBytes::clone_from_slice(self.digest().as_bytes());
+        aes.encrypt_block(&mut encrypted);
+        encrypted
+    }
+
+    pub fn update_header(&mut self, data: &HeaderBytes) {
+        let mut encrypted = self.encrypted_digest();
+        for (e, d) in encrypted.iter_mut().zip(data) {
+            *e ^= d;
+        }
+        self.hasher.update(&encrypted);
+    }
+
+    pub fn update_body(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        let prev = self.digest();
+        let mut encrypted = self.encrypted_digest();
+        for (e, p) in encrypted.iter_mut().zip(prev.as_bytes()) {
+            *e ^= p;
+        }
+        self.hasher.update(&encrypted);
+    }
 
     pub fn digest(&self) -> H128 {
         H128::from_slice(&self.hasher.clone().finalize()[0..16])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_matches_manual_seed() {
+        let secret = H256::repeat_byte(0x11);
+        let nonce = H256::repeat_byte(0x22);
+
+        let mut manual = MAC::new(secret);
+        manual.update((secret ^ nonce).as_bytes());
+        manual.update(b"auth packet");
+
+        let seeded = MAC::seeded(secret, nonce, b"auth packet");
+        assert_eq!(seeded.digest(), manual.digest());
+        let other_nonce = H256::repeat_byte(0x33);
+        assert_ne!(
+            seeded.digest(),
+            MAC::seeded(secret, other_nonce, b"auth packet").digest()
+        );
+    }
+
+    #[test]
+    fn keccak_matches_sha3() {
+        use sha3::Digest;
+
+        for len in [0, 1, 135, 136, 137, 272, 1000] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut hasher = Keccak256::new();
+            // Feed in uneven pieces to exercise buffering.
+            for piece in data.chunks(7) {
+                hasher.update(piece);
+            }
+            assert_eq!(
+                hasher.finalize().as_slice(),
+                sha3::Keccak256::digest(&data).as_slice(),
+                "len {len}"
+            );
+        }
+    }
+
+    #[test]
+    fn serialized_mac_resumes() {
+        let mut mac = MAC::new(H256::repeat_byte(0x42));
+        mac.update(&[1; 200]);
+        let mut restored = MAC::from_bytes(&mac.to_bytes()).unwrap();
+
+        mac.update_body(&[2; 32]);
+        restored.update_body(&[2; 32]);
+        assert_eq!(restored.digest(), mac.digest());
+        assert!(MAC::from_bytes(&[0; 10]).is_none());
+    }
+
+    #[test]
+    fn header_bytes_from_checks_length() {
+        let bytes: Vec<u8> = (0..16).collect();
+        let header = header_bytes_from(&bytes).unwrap();
+        assert_eq!(header_to_array(&header).as_slice(), bytes.as_slice());
+
+        assert!(matches!(
+            header_bytes_from(&bytes[..15]),
+            Err(ECIESEerror::InvalidHeader)
+        ));
+        assert!(matches!(
+            header_bytes_from(&[0; 17]),
+            Err(ECIESEerror::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let mut mac = MAC::new(H256::repeat_byte(0x42));
+        mac.update(b"seed");
+        let snapshot = mac.clone();
+        let before = snapshot.digest();
+
+        mac.update_header(&HeaderBytes::default());
+        mac.update_body(&[0_u8; 32]);
+
+        assert_ne!(mac.digest(), before);
+        assert_eq!(snapshot.digest(), before);
+    }
+}
@@ -0,0 +1,118 @@
+use super::DisconnectReason;
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+use std::collections::BTreeMap;
+
+/// A subprotocol name and version as advertised in `Hello`, e.g. `eth/68`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Capability {
+    pub name: String,
+    pub version: usize,
+}
+
+impl Capability {
+    pub fn new(name: impl Into<String>, version: usize) -> Self {
+        Self {
+            name: name.into(),
+            version,
+        }
+    }
+}
+
+impl Encodable for Capability {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(2);
+        s.append(&self.name);
+        s.append(&self.version);
+    }
+}
+
+impl Decodable for Capability {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        Ok(Self {
+            name: rlp.val_at(0)?,
+            version: rlp.val_at(1)?,
+        })
+    }
+}
+
+/// Capabilities both sides advertise. For every name, only the highest version present on
+/// both sides is kept; names without a common version are omitted. The result is ordered by
+/// name, which is the order message id offsets are assigned in.
+pub fn shared_capabilities(local: &[Capability], remote: &[Capability]) -> Vec<Capability> {
+    let mut shared = BTreeMap::<&str, usize>::new();
+    for capability in local
+        .iter()
+        .filter(|capability| remote.contains(capability))
+    {
+        let version = shared.entry(&capability.name).or_default();
+        *version = (*version).max(capability.version);
+    }
+
+    shared
+        .into_iter()
+        .map(|(name, version)| Capability::new(name, version))
+        .collect()
+}
+
+/// Runs [`shared_capabilities`] after the `Hello` exchange, failing with
+/// [`DisconnectReason::UselessPeer`] if the peer speaks none of our subprotocols.
+pub fn negotiate_capabilities(
+    local: &[Capability],
+    remote: &[Capability],
+) -> Result<Vec<Capability>, DisconnectReason> {
+    let shared = shared_capabilities(local, remote);
+    if shared.is_empty() {
+        return Err(DisconnectReason::UselessPeer);
+    }
+    Ok(shared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downgrades_to_highest_common_version() {
+        let local = [
+            Capability::new("eth", 66),
+            Capability::new("eth", 67),
+            Capability::new("eth", 68),
+        ];
+        let remote = [Capability::new("eth", 66)];
+
+        assert_eq!(
+            negotiate_capabilities(&local, &remote),
+            Ok(vec![Capability::new("eth", 66)])
+        );
+    }
+
+    #[test]
+    fn omits_names_without_common_version() {
+        let local = [Capability::new("eth", 68), Capability::new("snap", 1)];
+        let remote = [Capability::new("eth", 66), Capability::new("snap", 1)];
+
+        assert_eq!(
+            shared_capabilities(&local, &remote),
+            vec![Capability::new("snap", 1)]
+        );
+    }
+
+    #[test]
+    fn no_overlap_is_useless_peer() {
+        let local = [Capability::new("eth", 68)];
+        let remote = [Capability::new("eth", 66)];
+
+        assert_eq!(
+            negotiate_capabilities(&local, &remote),
+            Err(DisconnectReason::UselessPeer)
+        );
+    }
+
+    #[test]
+    fn capability_rlp_roundtrip() {
+        let capability = Capability::new("eth", 68);
+        let encoded = rlp::encode(&capability);
+        assert_eq!(encoded.to_vec(), hex::decode("c58365746844").unwrap());
+        assert_eq!(rlp::decode::<Capability>(&encoded).unwrap(), capability);
+    }
+}
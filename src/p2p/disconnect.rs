@@ -0,0 +1,125 @@
+use crate::util::decode_rlp;
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+use thiserror::Error;
+
+/// Reasons carried by the p2p `Disconnect` message.
+///
+/// A reason received from a peer must be decoded with [`DisconnectReason::decode_rlp`], which
+/// bounds its nesting depth before the [`Decodable`] impl walks it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Error)]
+pub enum DisconnectReason {
+    #[error("disconnect requested")]
+    DisconnectRequested = 0x00,
+    #[error("TCP sub-system error")]
+    TcpSubsystemError = 0x01,
+    #[error("breach of protocol, e.g. a malformed message or bad RLP")]
+    BreachOfProtocol = 0x02,
+    #[error("useless peer")]
+    UselessPeer = 0x03,
+    #[error("too many peers")]
+    TooManyPeers = 0x04,
+    #[error("already connected")]
+    AlreadyConnected = 0x05,
+    #[error("incompatible p2p protocol version")]
+    IncompatibleP2PProtocolVersion = 0x06,
+    #[error("null node identity received")]
+    NullNodeIdentity = 0x07,
+    #[error("client quitting")]
+    ClientQuitting = 0x08,
+    #[error("unexpected identity in handshake")]
+    UnexpectedHandshakeIdentity = 0x09,
+    #[error("identity is the same as this node")]
+    ConnectedToSelf = 0x0a,
+    #[error("ping timeout")]
+    PingTimeout = 0x0b,
+    #[error("some other reason specific to a subprotocol")]
+    SubprotocolSpecific = 0x10,
+}
+
+impl From<DisconnectReason> for u8 {
+    fn from(value: DisconnectReason) -> Self {
+        value as u8
+    }
+}
+
+impl TryFrom<u8> for DisconnectReason {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x00 => Self::DisconnectRequested,
+            0x01 => Self::TcpSubsystemError,
+            0x02 => Self::BreachOfProtocol,
+            0x03 => Self::UselessPeer,
+            0x04 => Self::TooManyPeers,
+            0x05 => Self::AlreadyConnected,
+            0x06 => Self::IncompatibleP2PProtocolVersion,
+            0x07 => Self::NullNodeIdentity,
+            0x08 => Self::ClientQuitting,
+            0x09 => Self::UnexpectedHandshakeIdentity,
+            0x0a => Self::ConnectedToSelf,
+            0x0b => Self::PingTimeout,
+            0x10 => Self::SubprotocolSpecific,
+            other => return Err(other),
+        })
+    }
+}
+
+impl DisconnectReason {
+    /// Decodes the body of a `Disconnect` received from a peer. See [`decode_rlp`].
+    pub fn decode_rlp(data: &[u8]) -> Result<Self, DecoderError> {
+        decode_rlp(data)
+    }
+}
+
+/// Encoded as the body of a `Disconnect` message, a single-element list.
+impl Encodable for DisconnectReason {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(1);
+        s.append(&u8::from(*self));
+    }
+}
+
+impl Decodable for DisconnectReason {
+    /// Only the first element is read, so elements a newer client appends are ignored. Some
+    /// clients send the reason as a bare integer rather than a list, which is accepted too.
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        let reason: u8 = if rlp.is_list() {
+            rlp.val_at(0)?
+        } else {
+            rlp.as_val()?
+        };
+        Self::try_from(reason).map_err(|_| DecoderError::Custom("unknown disconnect reason"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disconnect_rlp_roundtrip() {
+        let encoded = rlp::encode(&DisconnectReason::TooManyPeers);
+        assert_eq!(encoded.to_vec(), vec![0xc1, 0x04]);
+        assert_eq!(
+            DisconnectReason::decode_rlp(&encoded),
+            Ok(DisconnectReason::TooManyPeers)
+        );
+        assert_eq!(
+            DisconnectReason::decode_rlp(&[0x04]),
+            Ok(DisconnectReason::TooManyPeers)
+        );
+    }
+
+    #[test]
+    fn disconnect_ignores_trailing_elements() {
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&0x03_u8);
+        stream.append(&"extra");
+
+        assert_eq!(
+            DisconnectReason::decode_rlp(&stream.out()),
+            Ok(DisconnectReason::UselessPeer)
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use super::Capability;
+use crate::{types::PeerId, util::decode_rlp};
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+
+/// The first message each side sends after the handshake, describing itself and the
+/// subprotocols it speaks.
+///
+/// A `Hello` received from a peer must be decoded with [`HelloMessage::decode_rlp`], which
+/// bounds its nesting depth before the [`Decodable`] impl walks it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HelloMessage {
+    pub protocol_version: usize,
+    pub client_version: String,
+    pub capabilities: Vec<Capability>,
+    /// Listening port, or `0` if the node does not accept connections.
+    pub port: u16,
+    pub id: PeerId,
+}
+
+impl HelloMessage {
+    /// Decodes a `Hello` received from a peer. See [`decode_rlp`].
+    pub fn decode_rlp(data: &[u8]) -> Result<Self, DecoderError> {
+        decode_rlp(data)
+    }
+}
+
+impl Encodable for HelloMessage {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(5);
+        s.append(&self.protocol_version);
+        s.append(&self.client_version);
+        s.append_list(&self.capabilities);
+        s.append(&self.port);
+        s.append(&self.id);
+    }
+}
+
+impl Decodable for HelloMessage {
+    /// Reads the known fields by index, so elements a newer client appends are ignored as
+    /// EIP-8 requires.
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        Ok(Self {
+            protocol_version: rlp.val_at(0)?,
+            client_version: rlp.val_at(1)?,
+            capabilities: rlp.list_at(2)?,
+            port: rlp.val_at(3)?,
+            id: rlp.val_at(4)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello() -> HelloMessage {
+        HelloMessage {
+            protocol_version: 5,
+            client_version: "devp2p/0.1.0".to_string(),
+            capabilities: vec![Capability::new("eth", 68), Capability::new("snap", 1)],
+            port: 30303,
+            id: PeerId::repeat_byte(0xab),
+        }
+    }
+
+    #[test]
+    fn hello_roundtrip() {
+        let hello = hello();
+        assert_eq!(
+            HelloMessage::decode_rlp(&rlp::encode(&hello)).unwrap(),
+            hello
+        );
+    }
+
+    #[test]
+    fn hello_ignores_trailing_elements() {
+        let hello = hello();
+        let mut stream = RlpStream::new_list(6);
+        stream.append(&hello.protocol_version);
+        stream.append(&hello.client_version);
+        stream.append_list(&hello.capabilities);
+        stream.append(&hello.port);
+        stream.append(&hello.id);
+        stream.append(&"future field");
+
+        assert_eq!(HelloMessage::decode_rlp(&stream.out()).unwrap(), hello);
+    }
+}
@@ -0,0 +1,38 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use rlp::RlpStream;
+
+/// Message ids of the p2p protocol itself. Subprotocol messages are offset past these.
+pub const HELLO_ID: u8 = 0x00;
+pub const DISCONNECT_ID: u8 = 0x01;
+pub const PING_ID: u8 = 0x02;
+pub const PONG_ID: u8 = 0x03;
+
+/// Message id followed by an empty RLP list, the encoding of both `Ping` and `Pong`.
+fn empty_message(id: u8) -> Bytes {
+    let mut out = BytesMut::new();
+    out.put_slice(&rlp::encode(&id));
+    out.put_slice(&RlpStream::new_list(0).out());
+    out.freeze()
+}
+
+/// Encoded `Ping`, ready to be sent as the body of a frame. Lets tools keep a bare connection
+/// alive without running a session.
+pub fn ping_frame() -> Bytes {
+    empty_message(PING_ID)
+}
+
+/// Encoded `Pong`, the answer to [`ping_frame`].
+pub fn pong_frame() -> Bytes {
+    empty_message(PONG_ID)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_and_pong_encoding() {
+        assert_eq!(ping_frame().as_ref(), &[0x02, 0xc0]);
+        assert_eq!(pong_frame().as_ref(), &[0x03, 0xc0]);
+    }
+}
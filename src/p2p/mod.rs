@@ -0,0 +1,9 @@
+mod capability;
+mod disconnect;
+mod hello;
+mod message;
+
+pub use capability::*;
+pub use disconnect::*;
+pub use hello::*;
+pub use message::*;
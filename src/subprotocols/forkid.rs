@@ -0,0 +1,233 @@
+//! EIP-2124 fork identifiers, exchanged in the eth `Status` message so that peers on
+//! incompatible chains can be dropped before any sync traffic is sent.
+
+use crate::errors::ForkError;
+use crc32fast::Hasher;
+use ethereum_types::H256;
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+
+/// `FORK_HASH` / `FORK_NEXT` pair describing a node's current position on its fork schedule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ForkId {
+    /// CRC32 checksum of the genesis hash and all passed fork activation points.
+    pub hash: [u8; 4],
+    /// Next upcoming fork activation point, or `0` if none is known.
+    pub next: u64,
+}
+
+impl Encodable for ForkId {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(2);
+        s.append(&self.hash.as_slice());
+        s.append(&self.next);
+    }
+}
+
+impl Decodable for ForkId {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        let hash: Vec<u8> = rlp.val_at(0)?;
+        let hash = hash
+            .try_into()
+            .map_err(|_| DecoderError::Custom("fork hash must be 4 bytes"))?;
+        Ok(Self {
+            hash,
+            next: rlp.val_at(1)?,
+        })
+    }
+}
+
+/// Local fork schedule used to compute our own [`ForkId`] and to validate a remote one.
+///
+/// Fork activation points may be block numbers or timestamps, as long as `head` is expressed
+/// in the same unit when querying the filter.
+#[derive(Clone, Debug)]
+pub struct ForkFilter {
+    /// Sorted, deduplicated activation points, excluding forks active at genesis.
+    forks: Vec<u64>,
+    /// `sums[i]` is the checksum after the first `i` forks have been applied.
+    sums: Vec<u32>,
+}
+
+impl ForkFilter {
+    pub fn new(genesis: H256, forks: impl IntoIterator<Item = u64>) -> Self {
+        let mut forks: Vec<u64> = forks.into_iter().filter(|fork| *fork > 0).collect();
+        forks.sort_unstable();
+        forks.dedup();
+
+        let mut hasher = Hasher::new();
+        hasher.update(genesis.as_bytes());
+        let mut sums = Vec::with_capacity(forks.len() + 1);
+        sums.push(hasher.clone().finalize());
+        for fork in &forks {
+            hasher.update(&fork.to_be_bytes());
+            sums.push(hasher.clone().finalize());
+        }
+
+        Self { forks, sums }
+    }
+
+    /// Number of forks already passed at `head`.
+    fn passed(&self, head: u64) -> usize {
+        self.forks.partition_point(|fork| *fork <= head)
+    }
+
+    pub fn fork_id(&self, head: u64) -> ForkId {
+        let passed = self.passed(head);
+        ForkId {
+            hash: self.sums[passed].to_be_bytes(),
+            next: self.forks.get(passed).copied().unwrap_or(0),
+        }
+    }
+
+    /// Checks whether a peer announcing `remote` is compatible with us at `head`, following
+    /// the rules laid out in EIP-2124.
+    pub fn validate(&self, remote: ForkId, head: u64) -> Result<(), ForkError> {
+        let remote_sum = u32::from_be_bytes(remote.hash);
+        let passed = self.passed(head);
+
+        // 1) Same fork state: only reject if the remote announces a fork we already passed
+        //    without applying it.
+        if self.sums[passed] == remote_sum {
+            if remote.next > 0 && head >= remote.next {
+                return Err(ForkError::LocalIncompatibleOrStale);
+            }
+            return Ok(());
+        }
+
+        // 2) Remote is behind us: it must be aware of the fork that followed its state.
+        if let Some(i) = self.sums[..passed]
+            .iter()
+            .position(|sum| *sum == remote_sum)
+        {
+            if self.forks[i] != remote.next {
+                return Err(ForkError::RemoteStale);
+            }
+            return Ok(());
+        }
+
+        // 3) Remote is ahead of us: its state must be reachable with our future forks.
+        if self.sums[passed + 1..].contains(&remote_sum) {
+            return Ok(());
+        }
+
+        // 4) Remote is on a chain we know nothing about.
+        Err(ForkError::LocalIncompatibleOrStale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mainnet_genesis() -> H256 {
+        "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"
+            .parse()
+            .unwrap()
+    }
+
+    fn id(hash: u32, next: u64) -> ForkId {
+        ForkId {
+            hash: hash.to_be_bytes(),
+            next,
+        }
+    }
+
+    #[test]
+    fn mainnet_fork_ids() {
+        let filter = ForkFilter::new(
+            mainnet_genesis(),
+            [
+                1_150_000, 1_920_000, 2_463_000, 2_675_000, 4_370_000, 7_280_000, 7_280_000,
+                9_069_000, 9_200_000,
+            ],
+        );
+
+        let vectors = [
+            (0, id(0xfc64ec04, 1_150_000)),
+            (1_149_999, id(0xfc64ec04, 1_150_000)),
+            (1_150_000, id(0x97c2c34c, 1_920_000)),
+            (1_919_999, id(0x97c2c34c, 1_920_000)),
+            (1_920_000, id(0x91d1f948, 2_463_000)),
+            (2_462_999, id(0x91d1f948, 2_463_000)),
+            (2_463_000, id(0x7a64da13, 2_675_000)),
+            (2_674_999, id(0x7a64da13, 2_675_000)),
+            (2_675_000, id(0x3edd5b10, 4_370_000)),
+            (4_369_999, id(0x3edd5b10, 4_370_000)),
+            (4_370_000, id(0xa00bc324, 7_280_000)),
+            (7_279_999, id(0xa00bc324, 7_280_000)),
+            (7_280_000, id(0x668db0af, 9_069_000)),
+            (9_068_999, id(0x668db0af, 9_069_000)),
+            (9_069_000, id(0x879d6e30, 9_200_000)),
+            (9_199_999, id(0x879d6e30, 9_200_000)),
+            (9_200_000, id(0xe029e991, 0)),
+            (10_000_000, id(0xe029e991, 0)),
+        ];
+        for (head, expected) in vectors {
+            assert_eq!(filter.fork_id(head), expected, "head {head}");
+        }
+    }
+
+    #[test]
+    fn mainnet_validation() {
+        // Mainnet as of Petersburg, matching the validation vectors published in EIP-2124.
+        let filter = ForkFilter::new(
+            mainnet_genesis(),
+            [
+                1_150_000, 1_920_000, 2_463_000, 2_675_000, 4_370_000, 7_280_000,
+            ],
+        );
+
+        let vectors = [
+            (7_987_396, id(0x668db0af, 0), Ok(())),
+            (7_987_396, id(0x668db0af, u64::MAX), Ok(())),
+            (7_279_999, id(0xa00bc324, 0), Ok(())),
+            (7_279_999, id(0xa00bc324, 7_280_000), Ok(())),
+            (7_279_999, id(0xa00bc324, u64::MAX), Ok(())),
+            (7_987_396, id(0xa00bc324, 7_280_000), Ok(())),
+            (7_987_396, id(0x3edd5b10, 4_370_000), Ok(())),
+            (7_279_999, id(0x668db0af, 0), Ok(())),
+            (4_369_999, id(0xa00bc324, 0), Ok(())),
+            (7_987_396, id(0xa00bc324, 0), Err(ForkError::RemoteStale)),
+            (
+                7_987_396,
+                id(0x5cddc0e1, 0),
+                Err(ForkError::LocalIncompatibleOrStale),
+            ),
+            (
+                7_279_999,
+                id(0x5cddc0e1, 0),
+                Err(ForkError::LocalIncompatibleOrStale),
+            ),
+            (
+                7_987_396,
+                id(0xafec6b27, 0),
+                Err(ForkError::LocalIncompatibleOrStale),
+            ),
+            (
+                88_888_888,
+                id(0x668db0af, 88_888_888),
+                Err(ForkError::LocalIncompatibleOrStale),
+            ),
+            (
+                7_279_999,
+                id(0xa00bc324, 7_279_999),
+                Err(ForkError::LocalIncompatibleOrStale),
+            ),
+        ];
+        for (head, remote, expected) in vectors {
+            assert_eq!(
+                filter.validate(remote, head),
+                expected,
+                "head {head} remote {remote:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn fork_id_rlp_roundtrip() {
+        let fork_id = id(0xe029e991, 0);
+        let encoded = rlp::encode(&fork_id);
+        assert_eq!(encoded.to_vec(), hex::decode("c684e029e99180").unwrap());
+        assert_eq!(rlp::decode::<ForkId>(&encoded).unwrap(), fork_id);
+    }
+}
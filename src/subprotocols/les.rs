@@ -0,0 +1,300 @@
+//! The les light-client subprotocol, so far only its `Status` handshake.
+//!
+//! Unlike eth, les sends `Status` as a list of `[key, value]` pairs. Flags such as
+//! `serveHeaders` carry an empty list as their value, and keys a node does not know are
+//! skipped, so newer fields never break older peers.
+
+use super::forkid::ForkId;
+use crate::util::decode_rlp;
+use ethereum_types::{H256, U256};
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+
+/// Message id of `Status`, relative to the les offset negotiated in `Hello`.
+pub const STATUS_ID: u8 = 0x00;
+
+/// Cost of serving one request type, announced so clients can budget their buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestCost {
+    pub msg_code: u64,
+    pub base_cost: u64,
+    pub req_cost: u64,
+}
+
+impl Encodable for RequestCost {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(3);
+        s.append(&self.msg_code);
+        s.append(&self.base_cost);
+        s.append(&self.req_cost);
+    }
+}
+
+impl Decodable for RequestCost {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        Ok(Self {
+            msg_code: rlp.val_at(0)?,
+            base_cost: rlp.val_at(1)?,
+            req_cost: rlp.val_at(2)?,
+        })
+    }
+}
+
+/// Flow-control parameters a server announces: the client's buffer limit, how fast it
+/// recharges and what each request costs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlowControl {
+    pub buffer_limit: u64,
+    pub min_recharge: u64,
+    pub request_costs: Vec<RequestCost>,
+}
+
+/// The les `Status` message. Optional fields are only sent by servers, or by newer protocol
+/// versions.
+///
+/// A `Status` received from a peer must be decoded with [`Status::decode_rlp`], which bounds
+/// its nesting depth before the [`Decodable`] impl walks it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    pub protocol_version: u64,
+    pub network_id: u64,
+    pub head_td: U256,
+    pub head_hash: H256,
+    pub head_number: u64,
+    pub genesis_hash: H256,
+    /// Sent from les/4 on.
+    pub fork_id: Option<ForkId>,
+    /// How new heads are announced: `0` none, `1` simple, `2` signed.
+    pub announce_type: Option<u64>,
+    pub serve_headers: bool,
+    pub serve_chain_since: Option<u64>,
+    pub serve_state_since: Option<u64>,
+    pub tx_relay: bool,
+    pub flow_control: Option<FlowControl>,
+}
+
+impl Status {
+    /// Decodes a `Status` received from a peer. See [`decode_rlp`].
+    pub fn decode_rlp(data: &[u8]) -> Result<Self, DecoderError> {
+        decode_rlp(data)
+    }
+}
+
+fn append_pair<E: Encodable>(s: &mut RlpStream, key: &str, value: &E) {
+    s.begin_list(2);
+    s.append(&key);
+    s.append(value);
+}
+
+fn append_flag(s: &mut RlpStream, key: &str) {
+    s.begin_list(2);
+    s.append(&key);
+    s.begin_list(0);
+}
+
+impl Encodable for Status {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_unbounded_list();
+        append_pair(s, "protocolVersion", &self.protocol_version);
+        append_pair(s, "networkId", &self.network_id);
+        append_pair(s, "headTd", &self.head_td);
+        append_pair(s, "headHash", &self.head_hash);
+        append_pair(s, "headNum", &self.head_number);
+        append_pair(s, "genesisHash", &self.genesis_hash);
+        if let Some(fork_id) = &self.fork_id {
+            append_pair(s, "forkID", fork_id);
+        }
+        if let Some(announce_type) = &self.announce_type {
+            append_pair(s, "announceType", announce_type);
+        }
+        if self.serve_headers {
+            append_flag(s, "serveHeaders");
+        }
+        if let Some(since) = &self.serve_chain_since {
+            append_pair(s, "serveChainSince", since);
+        }
+        if let Some(since) = &self.serve_state_since {
+            append_pair(s, "serveStateSince", since);
+        }
+        if self.tx_relay {
+            append_flag(s, "txRelay");
+        }
+        if let Some(flow_control) = &self.flow_control {
+            append_pair(s, "flowControl/BL", &flow_control.buffer_limit);
+            s.begin_list(2);
+            s.append(&"flowControl/MRC");
+            s.append_list(&flow_control.request_costs);
+            append_pair(s, "flowControl/MRR", &flow_control.min_recharge);
+        }
+        s.finalize_unbounded_list();
+    }
+}
+
+fn required<T>(value: Option<T>) -> Result<T, DecoderError> {
+    value.ok_or(DecoderError::Custom("les status is missing a required key"))
+}
+
+impl Decodable for Status {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        let (mut protocol_version, mut network_id) = (None, None);
+        let (mut head_td, mut head_hash, mut head_number) = (None, None, None);
+        let (mut genesis_hash, mut fork_id, mut announce_type) = (None, None, None);
+        let (mut serve_headers, mut serve_chain_since, mut serve_state_since) = (false, None, None);
+        let mut tx_relay = false;
+        let (mut buffer_limit, mut request_costs, mut min_recharge) = (None, None, None);
+
+        for pair in rlp.iter() {
+            let key: String = pair.val_at(0)?;
+            let value = pair.at(1)?;
+            match key.as_str() {
+                "protocolVersion" => protocol_version = Some(value.as_val()?),
+                "networkId" => network_id = Some(value.as_val()?),
+                "headTd" => head_td = Some(value.as_val()?),
+                "headHash" => head_hash = Some(value.as_val()?),
+                "headNum" => head_number = Some(value.as_val()?),
+                "genesisHash" => genesis_hash = Some(value.as_val()?),
+                "forkID" => fork_id = Some(value.as_val()?),
+                "announceType" => announce_type = Some(value.as_val()?),
+                "serveHeaders" => serve_headers = true,
+                "serveChainSince" => serve_chain_since = Some(value.as_val()?),
+                "serveStateSince" => serve_state_since = Some(value.as_val()?),
+                "txRelay" => tx_relay = true,
+                "flowControl/BL" => buffer_limit = Some(value.as_val()?),
+                "flowControl/MRC" => request_costs = Some(value.as_list()?),
+                "flowControl/MRR" => min_recharge = Some(value.as_val()?),
+                _ => {}
+            }
+        }
+
+        let flow_control = match (buffer_limit, request_costs, min_recharge) {
+            (Some(buffer_limit), Some(request_costs), Some(min_recharge)) => Some(FlowControl {
+                buffer_limit,
+                min_recharge,
+                request_costs,
+            }),
+            (None, None, None) => None,
+            _ => return Err(DecoderError::Custom("incomplete les flow control")),
+        };
+
+        Ok(Self {
+            protocol_version: required(protocol_version)?,
+            network_id: required(network_id)?,
+            head_td: required(head_td)?,
+            head_hash: required(head_hash)?,
+            head_number: required(head_number)?,
+            genesis_hash: required(genesis_hash)?,
+            fork_id,
+            announce_type,
+            serve_headers,
+            serve_chain_since,
+            serve_state_since,
+            tx_relay,
+            flow_control,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::{shared_capabilities, Capability};
+
+    fn client_status() -> Status {
+        Status {
+            protocol_version: 4,
+            network_id: 1,
+            head_td: U256::from(17_000_000_u64) << 64,
+            head_hash: H256::repeat_byte(0xaa),
+            head_number: 17_034_870,
+            genesis_hash: H256::repeat_byte(0xd4),
+            fork_id: Some(ForkId {
+                hash: [0xf0, 0xaf, 0xd0, 0xe3],
+                next: 0,
+            }),
+            announce_type: Some(1),
+            serve_headers: false,
+            serve_chain_since: None,
+            serve_state_since: None,
+            tx_relay: false,
+            flow_control: None,
+        }
+    }
+
+    #[test]
+    fn client_status_roundtrip() {
+        let status = client_status();
+        assert_eq!(Status::decode_rlp(&rlp::encode(&status)).unwrap(), status);
+    }
+
+    #[test]
+    fn server_status_roundtrip() {
+        let status = Status {
+            serve_headers: true,
+            serve_chain_since: Some(0),
+            serve_state_since: Some(16_900_000),
+            tx_relay: true,
+            flow_control: Some(FlowControl {
+                buffer_limit: 300_000_000,
+                min_recharge: 50_000,
+                request_costs: vec![
+                    RequestCost {
+                        msg_code: 0x02,
+                        base_cost: 150_000,
+                        req_cost: 30_000,
+                    },
+                    RequestCost {
+                        msg_code: 0x04,
+                        base_cost: 0,
+                        req_cost: 700_000,
+                    },
+                ],
+            }),
+            ..client_status()
+        };
+        assert_eq!(Status::decode_rlp(&rlp::encode(&status)).unwrap(), status);
+    }
+
+    #[test]
+    fn skips_unknown_keys_and_requires_head() {
+        let status = client_status();
+
+        let mut with_unknown = RlpStream::new_list(7);
+        append_pair(
+            &mut with_unknown,
+            "protocolVersion",
+            &status.protocol_version,
+        );
+        append_pair(&mut with_unknown, "networkId", &status.network_id);
+        append_pair(&mut with_unknown, "headTd", &status.head_td);
+        append_pair(&mut with_unknown, "headHash", &status.head_hash);
+        append_pair(&mut with_unknown, "headNum", &status.head_number);
+        append_pair(&mut with_unknown, "genesisHash", &status.genesis_hash);
+        append_pair(&mut with_unknown, "futureKey", &"ignored");
+        let decoded = Status::decode_rlp(&with_unknown.out()).unwrap();
+        assert_eq!(decoded.head_number, status.head_number);
+        assert_eq!(decoded.fork_id, None);
+
+        let mut without_head = RlpStream::new_list(1);
+        append_pair(
+            &mut without_head,
+            "protocolVersion",
+            &status.protocol_version,
+        );
+        assert!(Status::decode_rlp(&without_head.out()).is_err());
+    }
+
+    #[test]
+    fn negotiates_eth_and_les_side_by_side() {
+        let local = [
+            Capability::new("eth", 67),
+            Capability::new("eth", 68),
+            Capability::new("les", 3),
+            Capability::new("les", 4),
+        ];
+        let remote = [Capability::new("les", 4), Capability::new("eth", 68)];
+
+        assert_eq!(
+            shared_capabilities(&local, &remote),
+            vec![Capability::new("eth", 68), Capability::new("les", 4)]
+        );
+    }
+}
@@ -0,0 +1,2 @@
+pub mod forkid;
+pub mod les;
@@ -0,0 +1,277 @@
+//! Splitting of messages across several RLPx frames.
+//!
+//! The first frame of a chunked message carries `[capability-id, context-id, total-size]` as
+//! its header data, following frames only `[capability-id, context-id]`. A frame whose context
+//! id has no message in progress is a complete message on its own.
+
+use crate::{
+    errors::ECIESEerror,
+    util::{check_rlp_depth, DEFAULT_MAX_RLP_DEPTH},
+};
+use bytes::{Bytes, BytesMut};
+use rlp::{Rlp, RlpStream};
+use std::collections::HashMap;
+
+/// The part of a message carried by a single frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    pub context_id: u16,
+    /// Size of the whole message; only set on the first chunk of a chunked message.
+    pub total_size: Option<usize>,
+    pub data: Bytes,
+}
+
+impl Chunk {
+    /// Encodes the header data to place in this chunk's frame header.
+    pub fn header_data(&self) -> BytesMut {
+        let mut stream = RlpStream::new_list(if self.total_size.is_some() { 3 } else { 2 });
+        stream.append(&0_u8);
+        stream.append(&self.context_id);
+        if let Some(total_size) = self.total_size {
+            stream.append(&total_size);
+        }
+        stream.out()
+    }
+
+    pub fn from_header_data(header_data: &[u8], data: Bytes) -> Result<Self, ECIESEerror> {
+        check_rlp_depth(header_data, DEFAULT_MAX_RLP_DEPTH)?;
+        let rlp = Rlp::new(header_data);
+        let total_size = match rlp.item_count()? {
+            2 => None,
+            3 => Some(rlp.val_at(2)?),
+            _ => return Err(ECIESEerror::InvalidHeader),
+        };
+        Ok(Self {
+            context_id: rlp.val_at(1)?,
+            total_size,
+            data,
+        })
+    }
+}
+
+/// Splits `body` into chunks of at most `chunk_size` bytes. A body that fits in one chunk is
+/// sent as a plain frame. Fails if `chunk_size` is zero.
+pub fn split(context_id: u16, body: Bytes, chunk_size: usize) -> Result<Vec<Chunk>, ECIESEerror> {
+    if chunk_size == 0 {
+        return Err(ECIESEerror::Other(anyhow::anyhow!(
+            "chunk size must be positive"
+        )));
+    }
+
+    if body.len() <= chunk_size {
+        return Ok(vec![Chunk {
+            context_id,
+            total_size: None,
+            data: body,
+        }]);
+    }
+
+    let total_size = body.len();
+    let mut chunks = Vec::with_capacity(total_size.div_ceil(chunk_size));
+    let mut offset = 0;
+    while offset < total_size {
+        let end = (offset + chunk_size).min(total_size);
+        chunks.push(Chunk {
+            context_id,
+            total_size: (offset == 0).then_some(total_size),
+            data: body.slice(offset..end),
+        });
+        offset = end;
+    }
+    Ok(chunks)
+}
+
+/// Collects chunks per context id until their message is complete.
+#[derive(Debug)]
+pub struct Reassembler {
+    max_size: usize,
+    max_buffered: usize,
+    /// Sum of the announced sizes of all messages in progress.
+    reserved: usize,
+    pending: HashMap<u16, (usize, BytesMut)>,
+}
+
+impl Reassembler {
+    /// `max_size` caps a single message, `max_buffered` the announced sizes of all messages
+    /// in progress together, so a peer cannot hold memory by opening many context ids.
+    pub fn new(max_size: usize, max_buffered: usize) -> Self {
+        Self {
+            max_size,
+            max_buffered,
+            reserved: 0,
+            pending: HashMap::new(),
+        }
+    }
+
+    fn remove(&mut self, context_id: u16) -> Option<BytesMut> {
+        let (total_size, buf) = self.pending.remove(&context_id)?;
+        self.reserved -= total_size;
+        Some(buf)
+    }
+
+    /// Feeds one received chunk, returning the full message once its last chunk arrives.
+    pub fn push(&mut self, chunk: Chunk) -> Result<Option<Bytes>, ECIESEerror> {
+        if let Some(total_size) = chunk.total_size {
+            if total_size > self.max_size {
+                return Err(ECIESEerror::OversizedFrame {
+                    size: total_size,
+                    max: self.max_size,
+                });
+            }
+            if self.pending.contains_key(&chunk.context_id) {
+                return Err(ECIESEerror::InvalidHeader);
+            }
+            let reserved = self.reserved.saturating_add(total_size);
+            if reserved > self.max_buffered {
+                return Err(ECIESEerror::OversizedFrame {
+                    size: reserved,
+                    max: self.max_buffered,
+                });
+            }
+            self.reserved = reserved;
+            // Allocate only what has arrived: the announced size comes from the peer.
+            self.pending
+                .insert(chunk.context_id, (total_size, BytesMut::new()));
+        }
+
+        let Some((total_size, buf)) = self.pending.get_mut(&chunk.context_id) else {
+            if chunk.data.len() > self.max_size {
+                return Err(ECIESEerror::OversizedFrame {
+                    size: chunk.data.len(),
+                    max: self.max_size,
+                });
+            }
+            return Ok(Some(chunk.data));
+        };
+
+        if buf.len() + chunk.data.len() > *total_size {
+            self.remove(chunk.context_id);
+            return Err(ECIESEerror::InvalidHeader);
+        }
+        buf.extend_from_slice(&chunk.data);
+
+        if buf.len() < *total_size {
+            return Ok(None);
+        }
+        Ok(self.remove(chunk.context_id).map(BytesMut::freeze))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecies::Secrets;
+    use ethereum_types::H256;
+
+    #[test]
+    fn three_chunk_roundtrip() {
+        let message = Bytes::from((0..=255_u8).cycle().take(2500).collect::<Vec<_>>());
+        let chunks = split(7, message.clone(), 1024).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].total_size, Some(2500));
+        assert!(chunks[1..].iter().all(|chunk| chunk.total_size.is_none()));
+
+        let new_secrets = || Secrets::from_keys(H256::repeat_byte(0x11), H256::repeat_byte(0x22));
+        let (mut egress, mut ingress) = (new_secrets(), new_secrets());
+        let mut wire = Vec::new();
+        for chunk in &chunks {
+            wire.extend(
+                egress
+                    .encode_frame_with_header_data(&chunk.header_data(), &chunk.data)
+                    .unwrap(),
+            );
+        }
+
+        let mut reassembler = Reassembler::new(4096, 4096);
+        let mut received = None;
+        let mut input = wire.as_slice();
+        while let Some((frame, consumed)) = ingress.decode_frame(input).unwrap() {
+            input = &input[consumed..];
+            let chunk = Chunk::from_header_data(&frame.header_data, frame.body).unwrap();
+            assert!(received.is_none());
+            received = reassembler.push(chunk).unwrap();
+        }
+        assert!(input.is_empty());
+        assert_eq!(received, Some(message));
+    }
+
+    #[test]
+    fn small_message_is_a_single_frame() {
+        let chunks = split(1, Bytes::from_static(b"ping"), 1024).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].total_size, None);
+
+        let mut reassembler = Reassembler::new(4096, 4096);
+        assert_eq!(
+            reassembler.push(chunks[0].clone()).unwrap(),
+            Some(Bytes::from_static(b"ping"))
+        );
+    }
+
+    #[test]
+    fn rejects_announced_size_over_cap() {
+        let mut reassembler = Reassembler::new(1024, 4096);
+        let chunk = Chunk {
+            context_id: 1,
+            total_size: Some(1 << 30),
+            data: Bytes::from_static(&[0; 16]),
+        };
+        assert!(matches!(
+            reassembler.push(chunk),
+            Err(ECIESEerror::OversizedFrame { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_chunks_beyond_announced_size() {
+        let mut reassembler = Reassembler::new(1024, 4096);
+        let first = Chunk {
+            context_id: 1,
+            total_size: Some(20),
+            data: Bytes::from_static(&[0; 16]),
+        };
+        let second = Chunk {
+            context_id: 1,
+            total_size: None,
+            data: Bytes::from_static(&[0; 16]),
+        };
+        assert_eq!(reassembler.push(first).unwrap(), None);
+        assert!(matches!(
+            reassembler.push(second),
+            Err(ECIESEerror::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn caps_memory_across_context_ids() {
+        let mut reassembler = Reassembler::new(1024, 2048);
+        let first = |context_id| Chunk {
+            context_id,
+            total_size: Some(1024),
+            data: Bytes::from_static(&[0; 16]),
+        };
+        assert_eq!(reassembler.push(first(1)).unwrap(), None);
+        assert_eq!(reassembler.push(first(2)).unwrap(), None);
+        assert!(matches!(
+            reassembler.push(first(3)),
+            Err(ECIESEerror::OversizedFrame {
+                size: 3072,
+                max: 2048
+            })
+        ));
+
+        // Completing a message frees its share.
+        let rest = Chunk {
+            context_id: 1,
+            total_size: None,
+            data: Bytes::from(vec![0; 1008]),
+        };
+        assert!(reassembler.push(rest).unwrap().is_some());
+        assert_eq!(reassembler.push(first(3)).unwrap(), None);
+    }
+
+    #[test]
+    fn zero_chunk_size_is_an_error() {
+        assert!(split(1, Bytes::from_static(b"ping"), 0).is_err());
+    }
+}
@@ -0,0 +1,3 @@
+mod chunk;
+
+pub use chunk::*;
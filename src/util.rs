@@ -0,0 +1,135 @@
+use crate::types::PeerId;
+use ethereum_types::H256;
+use hmac::{Hmac, Mac};
+use rlp::{Decodable, DecoderError, Rlp};
+use secp256k1::PublicKey;
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+
+pub fn keccak256(data: &[u8]) -> H256 {
+    H256::from_slice(&Keccak256::digest(data))
+}
+
+pub fn sha256(data: &[u8]) -> H256 {
+    H256::from_slice(&Sha256::digest(data))
+}
+
+pub fn hmac_sha256(key: &[u8], input: &[&[u8]], auth_data: &[u8]) -> H256 {
+    let mut hmac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    for input in input {
+        hmac.update(input);
+    }
+    hmac.update(auth_data);
+    H256::from_slice(&hmac.finalize().into_bytes())
+}
+
+/// Converts a public key into the 64-byte node id used on the wire.
+pub fn pk2id(pk: &PublicKey) -> PeerId {
+    PeerId::from_slice(&pk.serialize_uncompressed()[1..])
+}
+
+/// Converts a node id back into a public key, failing if it is not a point on the curve.
+pub fn id2pk(id: PeerId) -> Result<PublicKey, secp256k1::Error> {
+    let mut s = [0_u8; 65];
+    s[0] = 4;
+    s[1..].copy_from_slice(id.as_bytes());
+    PublicKey::from_slice(&s)
+}
+
+/// Nesting depth accepted in RLP received from a peer. Honest messages stay far below it.
+pub const DEFAULT_MAX_RLP_DEPTH: usize = 32;
+
+/// Error for RLP nested deeper than the limit passed to [`check_rlp_depth`].
+pub const RLP_TOO_DEEP: DecoderError = DecoderError::Custom("RLP nested too deeply");
+
+/// Fails with [`RLP_TOO_DEEP`] if the first item in `data` has lists nested more than
+/// `max_depth` levels. Anything after that item, such as EIP-8 padding, is not inspected.
+///
+/// The walk is iterative, so the check cannot itself exhaust the stack.
+pub fn check_rlp_depth(data: &[u8], max_depth: usize) -> Result<(), DecoderError> {
+    let item_len = |data: &[u8]| -> Result<(usize, usize), DecoderError> {
+        let info = Rlp::new(data).payload_info()?;
+        let total = info
+            .header_len
+            .checked_add(info.value_len)
+            .filter(|&total| total <= data.len())
+            .ok_or(DecoderError::RlpIsTooShort)?;
+        Ok((info.header_len, total))
+    };
+
+    let (_, end) = item_len(data)?;
+    // End offsets of the lists we are inside, innermost last.
+    let mut open = Vec::new();
+    let mut pos = 0;
+    while pos < end {
+        while open.last() == Some(&pos) {
+            open.pop();
+        }
+        let (header_len, len) = item_len(&data[pos..])?;
+        if open.last().is_some_and(|&list_end| pos + len > list_end) {
+            return Err(DecoderError::RlpIsTooShort);
+        }
+        if data[pos] >= 0xc0 {
+            if open.len() == max_depth {
+                return Err(RLP_TOO_DEEP);
+            }
+            open.push(pos + len);
+            pos += header_len;
+        } else {
+            pos += len;
+        }
+    }
+    Ok(())
+}
+
+/// [`rlp::decode`] for data received from a peer, rejecting it first if it is nested deeper
+/// than [`DEFAULT_MAX_RLP_DEPTH`].
+pub fn decode_rlp<T: Decodable>(data: &[u8]) -> Result<T, DecoderError> {
+    check_rlp_depth(data, DEFAULT_MAX_RLP_DEPTH)?;
+    rlp::decode(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::HelloMessage;
+
+    /// `depth` lists, each holding only the next; the innermost is empty.
+    fn nested_lists(depth: usize) -> Vec<u8> {
+        // Headers are computed innermost first, as each depends on the length it wraps.
+        let mut headers = Vec::with_capacity(depth);
+        let mut len = 0_usize;
+        for _ in 0..depth {
+            let header = if len < 56 {
+                vec![0xc0 + len as u8]
+            } else {
+                let len_bytes = len.to_be_bytes();
+                let len_bytes = &len_bytes[len.leading_zeros() as usize / 8..];
+                [&[0xf7 + len_bytes.len() as u8], len_bytes].concat()
+            };
+            len += header.len();
+            headers.push(header);
+        }
+        headers.into_iter().rev().flatten().collect()
+    }
+
+    #[test]
+    fn depth_limit_is_inclusive() {
+        assert_eq!(check_rlp_depth(&nested_lists(4), 4), Ok(()));
+        assert_eq!(check_rlp_depth(&nested_lists(5), 4), Err(RLP_TOO_DEEP));
+        assert_eq!(check_rlp_depth(&rlp::encode(&"flat"), 0), Ok(()));
+    }
+
+    #[test]
+    fn rejects_pathologically_nested_hello() {
+        let data = nested_lists(100_000);
+        assert_eq!(HelloMessage::decode_rlp(&data), Err(RLP_TOO_DEEP));
+    }
+
+    #[test]
+    fn ignores_trailing_padding() {
+        let mut data = nested_lists(2);
+        data.extend_from_slice(&[0xff; 8]);
+        assert_eq!(check_rlp_depth(&data, 2), Ok(()));
+    }
+}
@@ -0,0 +1,151 @@
+//! Handshake known-answer tests against the EIP-8 test vectors, as used by go-ethereum.
+//!
+//! Packets we produce carry a random ECIES key, IV and padding, so they cannot be compared
+//! byte-for-byte. Instead the published packets are parsed and the frame secrets derived from
+//! them are checked against the published values.
+
+use devp2p::{ecies::ECIES, util::pk2id};
+use ethereum_types::H256;
+use secp256k1::{PublicKey, SecretKey, SECP256K1};
+
+const STATIC_KEY_A: &str = "49a7b37aa6f6645917e7b807e9d1c00d4fa71f18343b0d4122a4d2df64dd6fee";
+const STATIC_KEY_B: &str = "b71c71a67e1177ad4e901695e1b4b9ee17ae16c6668d313eac2f96dbcda3f291";
+const EPHEMERAL_KEY_A: &str = "869d6ecf5211f1cc60418a13b9d870b22959d0c16f02bec714c960dd2298a32d";
+const EPHEMERAL_KEY_B: &str = "e238eb8e04fee6511ab04c6dd3c89ce097b11f25d584863ac2b6d5b35b1847e4";
+const NONCE_A: &str = "7e968bba13b6c50e2c4cd7f241cc0d64d1ac25c7f5952df231ac6a2bda8ee5d6";
+const NONCE_B: &str = "559aead08264d5795d3909718cdd05abd49572e84fe55590eef31a88a08fdffd";
+
+const AES_SECRET: &str = "80e8632c05fed6fc2a13b0f8d31a3cf645366239170ea067065aba8e28bac487";
+const MAC_SECRET: &str = "2ea74ec5dae199227dff1af715362700e989d889d7a493cb0639691efb8e5f98";
+/// Recipient's ingress MAC digest after also absorbing `foo`, once seeded from `AUTH_EIP8`.
+const FOO_INGRESS_HASH: &str = "0c7ec6340062cc46f5e9f1e3cf86f8c8c403c5a0964f5df0ebd34a75ddc86db5";
+
+/// `auth` from A to B in the pre-EIP-8 format.
+const AUTH_LEGACY: &str = "\
+    048ca79ad18e4b0659fab4853fe5bc58eb83992980f4c9cc147d2aa31532efd29a3d3dc6a3d89eaf913150cf\
+    c777ce0ce4af2758bf4810235f6e6ceccfee1acc6b22c005e9e3a49d6448610a58e98744ba3ac0399e82692d\
+    67c1f58849050b3024e21a52c9d3b01d871ff5f210817912773e610443a9ef142e91cdba0bd77b5fdf0769b0\
+    5671fc35f83d83e4d3b0b000c6b2a1b1bba89e0fc51bf4e460df3105c444f14be226458940d6061c29635093\
+    7ffd5e3acaceeaaefd3c6f74be8e23e0f45163cc7ebd76220f0128410fd05250273156d548a414444ae2f7de\
+    a4dfca2d43c057adb701a715bf59f6fb66b2d1d20f2c703f851cbf5ac47396d9ca65b6260bd141ac4d53e2de\
+    585a73d1750780db4c9ee4cd4d225173a4592ee77e2bd94d0be3691f3b406f9bba9b591fc63facc016bfa8";
+
+/// `auth` from A to B in the EIP-8 format.
+const AUTH_EIP8: &str = "\
+    01b304ab7578555167be8154d5cc456f567d5ba302662433674222360f08d5f1534499d3678b513b0fca474f\
+    3a514b18e75683032eb63fccb16c156dc6eb2c0b1593f0d84ac74f6e475f1b8d56116b849634a8c458705bf8\
+    3a626ea0384d4d7341aae591fae42ce6bd5c850bfe0b999a694a49bbbaf3ef6cda61110601d3b4c02ab6c304\
+    37257a6e0117792631a4b47c1d52fc0f8f89caadeb7d02770bf999cc147d2df3b62e1ffb2c9d8c125a398486\
+    5356266bca11ce7d3a688663a51d82defaa8aad69da39ab6d5470e81ec5f2a7a47fb865ff7cca21516f9299a\
+    07b1bc63ba56c7a1a892112841ca44b6e0034dee70c9adabc15d76a54f443593fafdc3b27af8059703f88928\
+    e199cb122362a4b35f62386da7caad09c001edaeb5f8a06d2b26fb6cb93c52a9fca51853b68193916982358f\
+    e1e5369e249875bb8d0d0ec36f917bc5e1eafd5896d46bd61ff23f1a863a8a8dcd54c7b109b771c8e61ec9c8\
+    908c733c0263440e2aa067241aaa433f0bb053c7b31a838504b148f570c0ad62837129e547678c5190341e4f\
+    1693956c3bf7678318e2d5b5340c9e488eefea198576344afbdf66db5f51204a6961a63ce072c8926c";
+
+/// `ack` from B to A in the EIP-8 format.
+const ACK_EIP8: &str = "\
+    01ea0451958701280a56482929d3b0757da8f7fbe5286784beead59d95089c217c9b917788989470b0e330cc\
+    6e4fb383c0340ed85fab836ec9fb8a49672712aeabbdfd1e837c1ff4cace34311cd7f4de05d59279e3524ab2\
+    6ef753a0095637ac88f2b499b9914b5f64e143eae548a1066e14cd2f4bd7f814c4652f11b254f8a2d0191e2f\
+    5546fae6055694aed14d906df79ad3b407d94692694e259191cde171ad542fc588fa2b7333313d82a9f88733\
+    2f1dfc36cea03f831cb9a23fea05b33deb999e85489e645f6aab1872475d488d7bd6c7c120caf28dbfc5d683\
+    3888155ed69d34dbdc39c1f299be1057810f34fbe754d021bfca14dc989753d61c413d261934e1a9c67ee060\
+    a25eefb54e81a4d14baff922180c395d3f998d70f46f6b58306f969627ae364497e73fc27f6d17ae45a413d3\
+    22cb8814276be6ddd13b885b201b943213656cde498fa0e9ddc8e0b8f8a53824fbd82254f3e2c17e8eaea009\
+    c38b4aa0a3f306e8797db43c25d68e86f262e564086f59a2fc60511c42abfb3057c247a8a8fe4fb3ccbadde1\
+    7514b7ac8000cdb6a912778426260c47f38919a91f25f4b5ffb455d6aaaf150f7e5529c100ce62d6d92826a7\
+    1778d809bdf60232ae21ce8a437eca8223f45ac37f6487452ce626f549b3b5fdee26afd2072e4bc75833c246\
+    4c805246155289f4";
+
+fn secret_key(hex: &str) -> SecretKey {
+    SecretKey::from_slice(&hex::decode(hex).unwrap()).unwrap()
+}
+
+fn h256(hex: &str) -> H256 {
+    H256::from_slice(&hex::decode(hex).unwrap())
+}
+
+fn id(secret_key: &SecretKey) -> devp2p::types::PeerId {
+    pk2id(&PublicKey::from_secret_key(SECP256K1, secret_key))
+}
+
+fn initiator() -> ECIES {
+    ECIES::new_static_client(
+        secret_key(STATIC_KEY_A),
+        id(&secret_key(STATIC_KEY_B)),
+        h256(NONCE_A),
+        secret_key(EPHEMERAL_KEY_A),
+    )
+    .unwrap()
+}
+
+fn recipient() -> ECIES {
+    ECIES::new_static_server(
+        secret_key(STATIC_KEY_B),
+        h256(NONCE_B),
+        secret_key(EPHEMERAL_KEY_B),
+    )
+    .unwrap()
+}
+
+fn assert_secrets(ecies: &ECIES) {
+    assert_eq!(ecies.debug_secrets(), (h256(AES_SECRET), h256(MAC_SECRET)));
+}
+
+#[test]
+fn recipient_accepts_eip8_auth() {
+    let mut recipient = recipient();
+    recipient
+        .parse_auth(&mut hex::decode(AUTH_EIP8).unwrap())
+        .unwrap();
+    assert_eq!(recipient.remote_id(), Some(id(&secret_key(STATIC_KEY_A))));
+
+    recipient.create_ack().unwrap();
+    assert_secrets(&recipient);
+
+    // The ingress MAC must be seeded with our nonce and the received auth; seeding it with the
+    // remote nonce or our own ack instead changes this digest.
+    let mut ingress_mac = recipient.secrets_mut().unwrap().ingress_mac().clone();
+    ingress_mac.update(b"foo");
+    assert_eq!(
+        ingress_mac.digest().as_bytes(),
+        &hex::decode(FOO_INGRESS_HASH).unwrap()[..16]
+    );
+}
+
+#[test]
+fn recipient_accepts_legacy_auth() {
+    let mut recipient = recipient();
+    recipient
+        .parse_auth(&mut hex::decode(AUTH_LEGACY).unwrap())
+        .unwrap();
+    assert_eq!(recipient.remote_id(), Some(id(&secret_key(STATIC_KEY_A))));
+
+    assert_eq!(recipient.create_ack().unwrap().len(), 210);
+    assert_secrets(&recipient);
+}
+
+#[test]
+fn initiator_accepts_eip8_ack() {
+    let mut initiator = initiator();
+    initiator.create_auth().unwrap();
+    initiator
+        .parse_ack(&mut hex::decode(ACK_EIP8).unwrap())
+        .unwrap();
+    assert_secrets(&initiator);
+}
+
+#[test]
+fn own_handshake_derives_published_secrets() {
+    let mut initiator = initiator();
+    let mut recipient = recipient();
+
+    let mut auth = initiator.create_auth().unwrap();
+    recipient.parse_auth(&mut auth).unwrap();
+    let mut ack = recipient.create_ack().unwrap();
+    initiator.parse_ack(&mut ack).unwrap();
+
+    assert_secrets(&initiator);
+    assert_secrets(&recipient);
+}